@@ -0,0 +1,466 @@
+use super::linalg::Matrix;
+use anyhow::Error;
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Zero};
+use std::fmt::{Debug, Display};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// Scalars a `Matrix` can hold. The elimination algorithms in `linalg.rs`
+/// (`rref`, `lu`, `det`, `inverse`, `rank`, `solve`) are written once against
+/// this trait, with hooks for the places where floating point needs extra
+/// care.
+pub trait Field:
+    Clone
+    + PartialEq
+    + Debug
+    + Display
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    fn zero() -> Self;
+
+    fn one() -> Self;
+
+    fn is_zero(&self) -> bool {
+        *self == Self::zero()
+    }
+
+    /// Absolute size, used to pick pivots and to scale tolerances.
+    fn magnitude(&self) -> f64;
+
+    /// Whether elimination may treat the entry as zero given the matrix's
+    /// tolerance.
+    fn is_negligible(&self, tol: f64) -> bool {
+        self.magnitude() <= tol
+    }
+
+    /// `self ^ exponent`, or `None` when the result is not in the field.
+    fn power(&self, exponent: &Self) -> Option<Self>;
+
+    fn from_integer(i: i32) -> Self;
+
+    /// Value of a decimal literal, `None` if it has none in the field.
+    fn from_double(d: f64) -> Option<Self>;
+
+    /// Value of a rational literal, `None` if it has none in the field.
+    fn from_rational(r: &BigRational) -> Option<Self>;
+
+    /// Value of a complex literal, `None` unless the field is complex.
+    fn from_complex(_z: &Complex64) -> Option<Self> {
+        None
+    }
+
+    /// Complex conjugate, which is the identity outside the complex numbers.
+    fn conj(&self) -> Self {
+        self.clone()
+    }
+
+    fn rank(m: &Matrix<Self>) -> usize {
+        m.elimination_rank()
+    }
+
+    fn solve(a: &Matrix<Self>, b: &Matrix<Self>) -> Result<Matrix<Self>, Error> {
+        a.solve_by_elimination(b)
+    }
+
+    /// Cleans up rounding noise in the determinant of an n by n matrix.
+    fn tidy_det(det: Self, _n: usize) -> Self {
+        det
+    }
+}
+
+/// The floating point fields, `f64` and `Complex64`. The orthogonal
+/// factorizations in `linalg.rs` (`qr`, `svd`, `pinv`, `chol`) are written
+/// once against this trait, with transposes as conjugate transposes.
+pub trait FloatField: Field + Copy {
+    fn from_real(x: f64) -> Self;
+
+    /// Real part.
+    fn real(&self) -> f64;
+}
+
+impl Field for BigRational {
+    fn zero() -> Self {
+        Zero::zero()
+    }
+
+    fn one() -> Self {
+        num_traits::One::one()
+    }
+
+    fn magnitude(&self) -> f64 {
+        self.abs().to_f64().unwrap_or(f64::INFINITY)
+    }
+
+    // exact, so only a true zero is ever skipped
+    fn is_negligible(&self, _tol: f64) -> bool {
+        Zero::is_zero(self)
+    }
+
+    fn power(&self, exponent: &Self) -> Option<Self> {
+        if !exponent.is_integer() {
+            return None;
+        }
+        let exponent = exponent.to_integer().to_i32()?;
+        if exponent < 0 && Zero::is_zero(self) {
+            return None;
+        }
+        Some(num_traits::Pow::pow(self, exponent))
+    }
+
+    fn from_integer(i: i32) -> Self {
+        BigRational::from_integer(BigInt::from(i))
+    }
+
+    fn from_double(d: f64) -> Option<Self> {
+        exact_decimal(d)
+    }
+
+    fn from_rational(r: &BigRational) -> Option<Self> {
+        Some(r.clone())
+    }
+}
+
+/// The decimal a float was written as, e.g. `0.1` is 1/10 rather than the
+/// binary fraction closest to it.
+pub fn exact_decimal(d: f64) -> Option<BigRational> {
+    if !d.is_finite() {
+        return None;
+    }
+    // Display gives the shortest digits that read back as `d`, never an
+    // exponent
+    let digits = d.to_string();
+    let (whole, frac) = digits.split_once('.').unwrap_or((&digits, ""));
+    let numer: BigInt = format!("{whole}{frac}").parse().ok()?;
+    let denom = num_traits::pow(BigInt::from(10), frac.len());
+    Some(BigRational::new(numer, denom))
+}
+
+/// Largest modulus `Modular` accepts, small enough that the product of two
+/// residues fits in an `i64`.
+pub const MAX_MODULUS: i64 = i32::MAX as i64;
+
+/// An element of GF(p). The modulus travels with the value so matrices over
+/// different primes can live side by side. Constants made without a modulus
+/// (`zero`, `one`, `from_integer`) have modulus 0 and stay plain integers
+/// until they meet a value that has one.
+#[derive(Clone, Copy, Debug)]
+pub struct Modular {
+    pub value: i64,
+    pub modulus: i64,
+}
+
+impl Modular {
+    pub fn new(value: i64, modulus: i64) -> Self {
+        let value = if modulus == 0 {
+            value
+        } else {
+            value.rem_euclid(modulus)
+        };
+        Modular { value, modulus }
+    }
+
+    /// `q` reduced mod `p`, `None` when p divides the denominator.
+    pub fn from_ratio(q: &BigRational, p: i64) -> Option<Self> {
+        let reduce = |i: &BigInt| (i % p).to_i64();
+        let numer = Modular::new(reduce(q.numer())?, p);
+        let denom = Modular::new(reduce(q.denom())?, p);
+        Some(numer * denom.inverse()?)
+    }
+
+    /// The same value given modulus `p` if it has none yet.
+    pub fn tagged(self, p: i64) -> Self {
+        if self.modulus == 0 {
+            Modular::new(self.value, p)
+        } else {
+            self
+        }
+    }
+
+    /// Both values brought to their shared modulus.
+    fn lift(self, other: Self) -> (Self, Self) {
+        assert!(
+            self.modulus == other.modulus || self.modulus == 0 || other.modulus == 0,
+            "cannot mix values mod {} and mod {}",
+            self.modulus,
+            other.modulus
+        );
+        let p = self.modulus.max(other.modulus);
+        (self.tagged(p), other.tagged(p))
+    }
+
+    /// Multiplicative inverse by the extended Euclidean algorithm. Without a
+    /// modulus only 1 and -1 have one.
+    fn inverse(self) -> Option<Self> {
+        if self.modulus == 0 {
+            return (self.value.abs() == 1).then_some(self);
+        }
+        let (mut r0, mut r1) = (self.modulus, self.value);
+        let (mut t0, mut t1) = (0, 1);
+        while r1 != 0 {
+            let q = r0 / r1;
+            (r0, r1) = (r1, r0 - q * r1);
+            (t0, t1) = (t1, t0 - q * t1);
+        }
+        (r0 == 1).then(|| Modular::new(t0, self.modulus))
+    }
+}
+
+/// Whether `p` is prime, which a modulus has to be for division to work.
+pub fn is_prime(p: i64) -> bool {
+    p >= 2 && (2..).take_while(|d| d * d <= p).all(|d| p % d != 0)
+}
+
+impl PartialEq for Modular {
+    fn eq(&self, other: &Self) -> bool {
+        let (a, b) = self.lift(*other);
+        a.value == b.value
+    }
+}
+
+impl Display for Modular {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+impl Add for Modular {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        let (a, b) = self.lift(other);
+        Modular::new(a.value + b.value, a.modulus)
+    }
+}
+
+impl Sub for Modular {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        let (a, b) = self.lift(other);
+        Modular::new(a.value - b.value, a.modulus)
+    }
+}
+
+impl Mul for Modular {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let (a, b) = self.lift(other);
+        Modular::new(a.value * b.value, a.modulus)
+    }
+}
+
+impl Div for Modular {
+    type Output = Self;
+
+    // callers check for a zero divisor, as with the other fields
+    #[allow(clippy::suspicious_arithmetic_impl)] // dividing is multiplying by the inverse
+    fn div(self, other: Self) -> Self {
+        let (a, b) = self.lift(other);
+        let inverse = b.inverse().expect("division by a non-invertible value");
+        a * inverse
+    }
+}
+
+impl Neg for Modular {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Modular::new(-self.value, self.modulus)
+    }
+}
+
+impl Field for Modular {
+    fn zero() -> Self {
+        Modular::new(0, 0)
+    }
+
+    fn one() -> Self {
+        Modular::new(1, 0)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.value == 0
+    }
+
+    fn magnitude(&self) -> f64 {
+        self.value.abs() as f64
+    }
+
+    // exact, so only a true zero is ever skipped
+    fn is_negligible(&self, _tol: f64) -> bool {
+        self.value == 0
+    }
+
+    // the exponent counts repetitions, so it is read as a plain integer
+    // rather than as a residue
+    fn power(&self, exponent: &Self) -> Option<Self> {
+        let base = self.tagged(exponent.modulus);
+        if base.modulus == 0 {
+            return None;
+        }
+        let mut square = if exponent.value < 0 {
+            base.inverse()?
+        } else {
+            base
+        };
+        let mut result = Modular::new(1, base.modulus);
+        let mut remaining = exponent.value.unsigned_abs();
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                result = result * square;
+            }
+            square = square * square;
+            remaining >>= 1;
+        }
+        Some(result)
+    }
+
+    fn from_integer(i: i32) -> Self {
+        Modular::new(i.into(), 0)
+    }
+
+    fn from_double(d: f64) -> Option<Self> {
+        (d.fract() == 0.0 && d.abs() <= MAX_MODULUS as f64).then(|| Modular::new(d as i64, 0))
+    }
+
+    fn from_rational(r: &BigRational) -> Option<Self> {
+        r.is_integer()
+            .then(|| r.to_integer().to_i64())
+            .flatten()
+            .map(|i| Modular::new(i, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratio(numer: i64, denom: i64) -> BigRational {
+        BigRational::new(numer.into(), denom.into())
+    }
+
+    fn modular(rows: usize, cols: usize, p: i64, data: &[i64]) -> Matrix<Modular> {
+        Matrix {
+            rows,
+            cols,
+            data: data.iter().map(|&x| Modular::new(x, p)).collect(),
+        }
+    }
+
+    #[test]
+    fn exact_decimal_reads_the_written_digits() {
+        assert_eq!(exact_decimal(0.1), Some(ratio(1, 10)));
+        assert_eq!(exact_decimal(-2.5), Some(ratio(-5, 2)));
+        assert_eq!(exact_decimal(3.0), Some(ratio(3, 1)));
+        assert_eq!(exact_decimal(1e-7), Some(ratio(1, 10_000_000)));
+        assert_eq!(exact_decimal(f64::NAN), None);
+        assert_eq!(exact_decimal(f64::INFINITY), None);
+    }
+
+    #[test]
+    fn modular_inverse() {
+        for a in 1..7 {
+            let inverse = Modular::new(a, 7).inverse().unwrap();
+            assert_eq!(inverse.modulus, 7);
+            assert_eq!((Modular::new(a, 7) * inverse).value, 1);
+        }
+        assert_eq!(Modular::new(0, 7).inverse(), None);
+        assert_eq!(Modular::new(3, 7).inverse().unwrap().value, 5);
+        // only units have inverses when the modulus is not prime
+        assert_eq!(Modular::new(2, 6).inverse(), None);
+        assert_eq!(Modular::new(-1, 0).inverse().unwrap().value, -1);
+        assert_eq!(Modular::new(2, 0).inverse(), None);
+    }
+
+    #[test]
+    fn modular_from_ratio() {
+        assert_eq!(Modular::from_ratio(&ratio(1, 3), 7).unwrap().value, 5);
+        assert_eq!(Modular::from_ratio(&ratio(-1, 2), 7).unwrap().value, 3);
+        assert_eq!(Modular::from_ratio(&ratio(7, 2), 7).unwrap().value, 0);
+        assert_eq!(Modular::from_ratio(&ratio(1, 14), 7), None);
+        assert_eq!(Modular::from_ratio(&ratio(3, 7), 7), None);
+    }
+
+    #[test]
+    fn modular_power() {
+        let three = Modular::new(3, 7);
+        let power = |base: Modular, exponent: i64| base.power(&Modular::new(exponent, 0));
+        assert_eq!(power(three, 2).unwrap().value, 2);
+        assert_eq!(power(three, 6).unwrap().value, 1);
+        assert_eq!(power(three, 0).unwrap().value, 1);
+        assert_eq!(power(three, -1).unwrap().value, 5);
+        assert_eq!(power(three, -2).unwrap().value, 4);
+        assert_eq!(power(Modular::new(0, 7), -1), None);
+        // without a modulus there is no field to take the power in
+        assert_eq!(power(Modular::new(3, 0), 2), None);
+    }
+
+    #[test]
+    fn untagged_values_take_the_other_modulus() {
+        let six = Modular::new(6, 7);
+        let sum = Modular::one() + six;
+        assert_eq!((sum.value, sum.modulus), (0, 7));
+        let product = Modular::from_integer(-2) * six;
+        assert_eq!((product.value, product.modulus), (2, 7));
+        assert_eq!(Modular::zero(), Modular::new(7, 7));
+        let plain = Modular::from_integer(3) * Modular::from_integer(5);
+        assert_eq!((plain.value, plain.modulus), (15, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot mix values mod 5 and mod 7")]
+    fn mixed_moduli_panic() {
+        let _ = Modular::new(1, 5) + Modular::new(1, 7);
+    }
+
+    #[test]
+    fn elimination_over_gf2() {
+        // generator of the [7, 4] Hamming code, already in reduced form
+        let g = modular(
+            4,
+            7,
+            2,
+            &[
+                1, 0, 0, 0, 1, 1, 0, 0, 1, 0, 0, 1, 0, 1, 0, 0, 1, 0, 0, 1, 1, 0, 0, 0, 1, 1, 1, 1,
+            ],
+        );
+        assert_eq!(g.rank(), 4);
+        assert_eq!(g.rref(), g);
+        assert_eq!(g.transpose().rank(), 4);
+
+        let a = modular(2, 2, 2, &[1, 1, 1, 0]);
+        assert_eq!(a.det().unwrap().value, 1);
+        assert_eq!(a.inverse().unwrap(), modular(2, 2, 2, &[0, 1, 1, 1]));
+
+        // invertible over the rationals, but 1 = -1 here
+        let b = modular(2, 2, 2, &[1, 1, 1, -1]);
+        assert_eq!(b.det().unwrap().value, 0);
+        assert_eq!(b.rank(), 1);
+        assert_eq!(b.rref(), modular(2, 2, 2, &[1, 1, 0, 0]));
+        assert!(b.inverse().is_err());
+    }
+
+    #[test]
+    fn elimination_over_gf7() {
+        let a = modular(2, 2, 7, &[2, 1, 1, 3]);
+        assert_eq!(a.det().unwrap().value, 5);
+        let inverse = a.inverse().unwrap();
+        assert_eq!(inverse, modular(2, 2, 7, &[2, 4, 4, 6]));
+        assert_eq!(a.dot(inverse).unwrap(), Matrix::identity(2));
+        assert_eq!(a.rank(), 2);
+
+        // det is -7, so singular only mod 7
+        let b = modular(2, 2, 7, &[1, 2, 3, -1]);
+        assert_eq!(b.det().unwrap().value, 0);
+        assert_eq!(b.rank(), 1);
+        assert_eq!(b.rref(), modular(2, 2, 7, &[1, 2, 0, 0]));
+        assert!(b.inverse().is_err());
+    }
+}
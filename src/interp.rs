@@ -1,7 +1,19 @@
-use super::parser::*;
+use super::field::{exact_decimal, is_prime, Field, Modular, MAX_MODULUS};
+use super::linalg::{Eigen, Matrix};
+use super::parser::{self, *};
 use super::types::*;
 use anyhow::{anyhow, Error};
-use std::{collections::HashMap, ops::Deref};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+use rand::{rngs::StdRng, SeedableRng};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    ops::{Deref, Range},
+    path::{Path, PathBuf},
+};
+use typed_arena::Arena;
 
 #[inline]
 fn get_value<'a>(
@@ -25,13 +37,168 @@ where
 {
     match expr {
         AstNode::Ident(id) => get_value(env, id),
+        AstNode::Integer(i) => Ok(LalaType::Integer(*i)),
+        AstNode::DoublePrecisionFloat(d) => match (mode(), exact_decimal(*d)) {
+            (Mode::Rational, Some(q)) => Ok(LalaType::Rational(q)),
+            (Mode::Modular(_), Some(q)) => rational(q),
+            _ => Ok(LalaType::Double(*d)),
+        },
+        AstNode::Rational(q) => rational(q.clone()),
+        AstNode::Complex(z) => Ok(LalaType::Complex(*z)),
         AstNode::MonadicOp { verb, expr } => eval_monadic_op(expr, env, verb),
         AstNode::DyadicOp { verb, lhs, rhs } => eval_dyadic_op(lhs, rhs, env, verb),
-        AstNode::Matrix(m) => Ok(LalaType::Matrix(construct_matrix(m)?)),
+        AstNode::Matrix(m) => literal_matrix(m),
+        AstNode::App((name, params)) => interp_app(name, params, env),
+        AstNode::Index { ident, rows, cols } => {
+            let value = get_value(env, ident)?;
+            let (nrows, ncols) = shape(&value)
+                .ok_or_else(|| anyhow!("can only index into a matrix, {ident} is not one"))?;
+            let (row_range, single_row) = eval_slice(env, rows, nrows, "row")?;
+            let (col_range, single_col) = eval_slice(env, cols, ncols, "column")?;
+            let single = single_row && single_col;
+            match value {
+                LalaType::Matrix(m) => pick(&m, row_range, col_range, single),
+                LalaType::RationalMatrix(m) => pick(&m, row_range, col_range, single),
+                LalaType::ComplexMatrix(m) => pick(&m, row_range, col_range, single),
+                LalaType::ModularMatrix(m) => pick(&m, row_range, col_range, single),
+                _ => unreachable!("{ident} has a shape so it is a matrix"),
+            }
+        }
+        // a single row of numbers is a row vector
+        AstNode::Terms(row) => literal_matrix(std::slice::from_ref(row)),
+        AstNode::Tuple(items) => Ok(LalaType::Tuple(
+            items
+                .iter()
+                .map(|item| eval_expr(env, item, func))
+                .collect::<Result<Vec<_>, Error>>()?,
+        )),
         _ => Err(anyhow!("error processing {func} consult the docs.")),
     }
 }
 
+/// A rational literal. Whole numbers are plain integers, anything else is
+/// only kept exact in rational mode and reduced in mod p mode.
+fn rational<'a>(q: BigRational) -> Result<LalaType<'a>, Error> {
+    if let Some(i) = q.is_integer().then(|| q.to_integer().to_i32()).flatten() {
+        return Ok(LalaType::Integer(i));
+    }
+    Ok(match mode() {
+        Mode::Rational => LalaType::Rational(q),
+        Mode::Modular(p) => LalaType::Modular(
+            Modular::from_ratio(&q, p).ok_or_else(|| anyhow!("{q} has no value mod {p}"))?,
+        ),
+        Mode::Float => LalaType::Double(q.to_f64().unwrap_or(f64::NAN)),
+    })
+}
+
+fn literal_matrix<'a>(rows: &[Vec<AstNode>]) -> Result<LalaType<'a>, Error> {
+    let complex = rows
+        .iter()
+        .flatten()
+        .any(|entry| matches!(entry, AstNode::Complex(_)));
+    if complex {
+        return Ok(LalaType::ComplexMatrix(construct_matrix(rows)?));
+    }
+    Ok(match mode() {
+        Mode::Float => LalaType::Matrix(construct_matrix(rows)?),
+        Mode::Rational => LalaType::RationalMatrix(construct_matrix(rows)?),
+        // read exactly first so fractions become inverses mod p
+        Mode::Modular(p) => {
+            let exact = LalaType::RationalMatrix(construct_matrix(rows)?);
+            match modular_operand(&exact, p) {
+                Some(Operand::Matrix(m)) => LalaType::ModularMatrix(m),
+                _ => return Err(anyhow!("{exact}has an entry with no value mod {p}")),
+            }
+        }
+    })
+}
+
+fn shape(value: &LalaType) -> Option<(usize, usize)> {
+    match value {
+        LalaType::Matrix(m) => Some((m.rows, m.cols)),
+        LalaType::RationalMatrix(m) => Some((m.rows, m.cols)),
+        LalaType::ComplexMatrix(m) => Some((m.rows, m.cols)),
+        LalaType::ModularMatrix(m) => Some((m.rows, m.cols)),
+        _ => None,
+    }
+}
+
+/// The block of `m` picked out by an index, a scalar if it is one entry.
+fn pick<'a, T: Field>(
+    m: &Matrix<T>,
+    rows: Range<usize>,
+    cols: Range<usize>,
+    single: bool,
+) -> Result<LalaType<'a>, Error>
+where
+    LalaType<'a>: From<Matrix<T>> + From<T>,
+{
+    let sub = m.submatrix(rows, cols)?;
+    Ok(if single {
+        sub[0][0].clone().into()
+    } else {
+        sub.into()
+    })
+}
+
+/// Closest floating point matrix, for verbs that have no exact algorithm.
+fn float_matrix(m: &Matrix<BigRational>) -> Matrix {
+    m.map(|x| x.to_f64().unwrap_or(f64::NAN))
+}
+
+fn eval_position<'a, 'b>(
+    env: &mut HashMap<String, LalaType<'a>>,
+    node: &'b AstNode<'b>,
+) -> Result<usize, Error>
+where
+    'b: 'a,
+{
+    match eval_expr(env, node, "index")? {
+        LalaType::Integer(i) if i >= 0 => Ok(i as usize),
+        other => Err(anyhow!("{other} is not a valid index")),
+    }
+}
+
+/// Resolves a slice against an axis of length `len`, also reporting whether
+/// it picked a single position.
+fn eval_slice<'a, 'b>(
+    env: &mut HashMap<String, LalaType<'a>>,
+    slice: &'b Slice<'b>,
+    len: usize,
+    axis: &str,
+) -> Result<(std::ops::Range<usize>, bool), Error>
+where
+    'b: 'a,
+{
+    let (range, single) = match slice {
+        Slice::At(at) => {
+            let at = eval_position(env, at)?;
+            (at..at + 1, true)
+        }
+        Slice::Range(lower, upper) => {
+            let lower = match lower {
+                Some(lower) => eval_position(env, lower)?,
+                None => 0,
+            };
+            let upper = match upper {
+                Some(upper) => eval_position(env, upper)?,
+                None => len,
+            };
+            (lower..upper, false)
+        }
+    };
+    if range.end > len {
+        return Err(anyhow!(
+            "{axis} index {} out of bounds for a matrix with {len} {axis}s",
+            range.end - 1
+        ));
+    }
+    if range.is_empty() {
+        return Err(anyhow!("empty {axis} range {}:{}", range.start, range.end));
+    }
+    Ok((range, single))
+}
+
 fn eval_monadic_op<'a, 'expr>(
     expr: &'expr AstNode<'expr>,
     env: &mut HashMap<String, LalaType<'a>>,
@@ -41,8 +208,34 @@ where
     'expr: 'a,
 {
     let func = verb.to_string();
-    let matrix = match eval_expr(env, expr, &func)? {
+    let value = eval_expr(env, expr, &func)?;
+    if *verb == MonadicVerb::Eye {
+        let n = dimension(&value, &func)?;
+        return Ok(match mode() {
+            Mode::Float => LalaType::Matrix(Matrix::identity(n)),
+            Mode::Rational => LalaType::RationalMatrix(Matrix::identity(n)),
+            Mode::Modular(p) => tag_modulus(LalaType::ModularMatrix(Matrix::identity(n)), p),
+        });
+    }
+    let matrix = match value {
         LalaType::Matrix(mat) => mat,
+        LalaType::RationalMatrix(mat) => match field_monadic(&mat, verb)? {
+            Some(result) => return Ok(result),
+            // no exact algorithm, so fall back to floating point
+            None => float_matrix(&mat),
+        },
+        LalaType::ComplexMatrix(mat) => {
+            return match field_monadic(&mat, verb)? {
+                Some(result) => Ok(result),
+                None => complex_monadic(&mat, verb),
+            }
+        }
+        LalaType::ModularMatrix(mat) => {
+            let p = matrix_modulus(&mat);
+            let result = field_monadic(&mat, verb)?
+                .ok_or_else(|| anyhow!("{func} is not available for matrices mod {p}"))?;
+            return Ok(tag_modulus(result, p));
+        }
         _ => {
             return Err(anyhow!(
                 "monadic op {} cna only be used on a matrix",
@@ -50,21 +243,134 @@ where
             ))
         }
     };
+    if let Some(result) = field_monadic(&matrix, verb)? {
+        return Ok(result);
+    }
     Ok(match verb {
-        MonadicVerb::Inverse => match matrix.inverse() {
-            Ok(result) => LalaType::Matrix(result),
-            Err(e) => return Err(e),
+        MonadicVerb::Qr => {
+            let (q, r) = matrix.qr();
+            LalaType::Tuple(vec![LalaType::Matrix(q), LalaType::Matrix(r)])
+        }
+        MonadicVerb::Eigen => match matrix.eig()? {
+            Eigen::Real(values, vectors) => LalaType::Tuple(vec![values.into(), vectors.into()]),
+            Eigen::Complex(values, vectors) => LalaType::Tuple(vec![values.into(), vectors.into()]),
         },
+        MonadicVerb::Svd => {
+            let (u, sigma, vt) = matrix.svd();
+            LalaType::Tuple(vec![
+                LalaType::Matrix(u),
+                LalaType::Matrix(sigma),
+                LalaType::Matrix(vt),
+            ])
+        }
+        MonadicVerb::PseudoInverse => LalaType::Matrix(matrix.pinv()),
+        MonadicVerb::Cholesky => LalaType::Matrix(matrix.chol()?),
+        MonadicVerb::Ldl => {
+            let (l, d, p) = matrix.ldl()?;
+            LalaType::Tuple(vec![
+                LalaType::Matrix(l),
+                LalaType::Matrix(d),
+                LalaType::Matrix(p),
+            ])
+        }
+        MonadicVerb::Norm => LalaType::Double(matrix.norm()),
+        MonadicVerb::Norm1 => LalaType::Double(matrix.norm_1()),
+        MonadicVerb::NormInf => LalaType::Double(matrix.norm_inf()),
+        MonadicVerb::NormFrobenius => LalaType::Double(matrix.norm_frobenius()),
+        MonadicVerb::Cond => LalaType::Double(matrix.cond()),
+        _ => unreachable!("{func} is handled above"),
+    })
+}
+
+/// Monadic verbs built on elimination, which work over any field. `None`
+/// for verbs that only exist in floating point.
+fn field_monadic<'a, T: Field>(
+    matrix: &Matrix<T>,
+    verb: &MonadicVerb,
+) -> Result<Option<LalaType<'a>>, Error>
+where
+    LalaType<'a>: From<Matrix<T>> + From<T>,
+{
+    Ok(Some(match verb {
+        MonadicVerb::Inverse => matrix.inverse()?.into(),
         MonadicVerb::Rank => LalaType::Integer(matrix.rank()),
-        MonadicVerb::RREF => LalaType::Matrix(matrix.rref()),
-        MonadicVerb::Transpose => LalaType::Matrix(matrix.transpose()),
-        MonadicVerb::Determinant => match matrix.det() {
-            Ok(result) => LalaType::Double(result),
-            Err(e) => return Err(e),
-        },
+        MonadicVerb::Rref => matrix.rref().into(),
+        MonadicVerb::RrefSteps => {
+            let (_, steps) = matrix.rref_steps();
+            LalaType::Steps {
+                start: Box::new(matrix.clone().into()),
+                steps: steps
+                    .into_iter()
+                    .map(|(op, m)| (op.map(Into::into), m.into()))
+                    .collect(),
+            }
+        }
+        MonadicVerb::Transpose => matrix.transpose().into(),
+        MonadicVerb::ConjugateTranspose => matrix.conjugate_transpose().into(),
+        MonadicVerb::Determinant => matrix.det()?.into(),
+        MonadicVerb::Lu => {
+            let (l, u, p) = matrix.lu();
+            LalaType::Tuple(vec![l.into(), u.into(), p.into()])
+        }
+        MonadicVerb::Diag => matrix.diag().into(),
+        MonadicVerb::Trace => matrix.trace()?.into(),
+        MonadicVerb::NullSpace => matrix.null_space().into(),
+        MonadicVerb::ColumnSpace => matrix.column_space().into(),
+        MonadicVerb::RowSpace => matrix.row_space().into(),
+        _ => return Ok(None),
+    }))
+}
+
+/// The floating point verbs of `eval_monadic_op` for complex matrices.
+fn complex_monadic<'a>(
+    matrix: &Matrix<Complex64>,
+    verb: &MonadicVerb,
+) -> Result<LalaType<'a>, Error> {
+    Ok(match verb {
+        MonadicVerb::Qr => {
+            let (q, r) = matrix.qr();
+            LalaType::Tuple(vec![q.into(), r.into()])
+        }
+        MonadicVerb::Eigen => {
+            let (values, vectors) = matrix.eig()?;
+            LalaType::Tuple(vec![values.into(), vectors.into()])
+        }
+        MonadicVerb::Svd => {
+            let (u, sigma, vh) = matrix.svd();
+            LalaType::Tuple(vec![u.into(), sigma.into(), vh.into()])
+        }
+        MonadicVerb::PseudoInverse => matrix.pinv().into(),
+        MonadicVerb::Cholesky => matrix.chol()?.into(),
+        MonadicVerb::Norm => LalaType::Double(matrix.norm()),
+        MonadicVerb::Norm1 => LalaType::Double(matrix.norm_1()),
+        MonadicVerb::NormInf => LalaType::Double(matrix.norm_inf()),
+        MonadicVerb::NormFrobenius => LalaType::Double(matrix.norm_frobenius()),
+        MonadicVerb::Cond => LalaType::Double(matrix.cond()),
+        _ => return Err(anyhow!("{verb} is not available for complex matrices")),
     })
 }
 
+/// Dyadic verbs between two matrices that work over any field. `None` for
+/// verbs that only exist in floating point.
+fn field_dyadic<'a, T: Field>(
+    left: &Matrix<T>,
+    right: &Matrix<T>,
+    verb: &DyadicVerb,
+) -> Result<Option<LalaType<'a>>, Error>
+where
+    LalaType<'a>: From<Matrix<T>>,
+{
+    Ok(Some(match verb {
+        DyadicVerb::Dot => left.dot(right.clone())?.into(),
+        DyadicVerb::Plus => left.combine(right.clone(), |a, b| a + b)?.into(),
+        DyadicVerb::Times => left.combine(right.clone(), |a, b| a * b)?.into(),
+        DyadicVerb::Solve => left.solve(right)?.into(),
+        DyadicVerb::HorizontalConcat => left.hstack(right)?.into(),
+        DyadicVerb::VerticalConcat => left.vstack(right)?.into(),
+        _ => return Ok(None),
+    }))
+}
+
 fn eval_dyadic_op<'a, 'lhs, 'rhs>(
     lhs: &'lhs AstNode<'lhs>,
     rhs: &'rhs AstNode<'rhs>,
@@ -76,102 +382,515 @@ where
     'rhs: 'a,
 {
     let func = verb.to_string();
-    let leftside = if let LalaType::Matrix(left) = eval_expr(env, lhs, &func)? {
-        left
-    } else {
-        return Err(anyhow!("can only call {func} on a matrix"));
-    };
-    let rightside = if let LalaType::Matrix(right) = eval_expr(env, rhs, &func)? {
-        right
-    } else {
-        return Err(anyhow!("can only call {func} on a matrix"));
+    let leftside = eval_expr(env, lhs, &func)?;
+    let rightside = eval_expr(env, rhs, &func)?;
+    match verb {
+        DyadicVerb::Scale => return scale(leftside, rightside),
+        DyadicVerb::Add
+        | DyadicVerb::Subtract
+        | DyadicVerb::Multiply
+        | DyadicVerb::Divide
+        | DyadicVerb::Power => return arithmetic(verb, leftside, rightside),
+        DyadicVerb::Modulo => {
+            let p = match leftside {
+                LalaType::Integer(p) => prime_modulus(p.into())?,
+                _ => return Err(anyhow!("the modulus has to be an integer, got {leftside}")),
+            };
+            return match modular_operand(&rightside, p) {
+                Some(Operand::Scalar(x)) => Ok(LalaType::Modular(x)),
+                Some(Operand::Matrix(m)) => Ok(LalaType::ModularMatrix(m)),
+                None => Err(anyhow!("{rightside} has no value mod {p}")),
+            };
+        }
+        DyadicVerb::Zeros | DyadicVerb::Ones | DyadicVerb::Random => {
+            let rows = dimension(&leftside, &func)?;
+            let cols = dimension(&rightside, &func)?;
+            let random = || RNG.with(|rng| Matrix::random(rows, cols, &mut *rng.borrow_mut()));
+            return Ok(match (verb, mode()) {
+                // uniform residues, so rand stays usable for codes
+                (DyadicVerb::Random, Mode::Modular(p)) => LalaType::ModularMatrix(
+                    random().map(|x| Modular::new((x * p as f64) as i64, p)),
+                ),
+                (DyadicVerb::Random, _) => LalaType::Matrix(random()),
+                (DyadicVerb::Zeros, Mode::Float) => LalaType::Matrix(Matrix::new(rows, cols)),
+                (DyadicVerb::Zeros, Mode::Rational) => {
+                    LalaType::RationalMatrix(Matrix::new(rows, cols))
+                }
+                (_, Mode::Float) => LalaType::Matrix(Matrix::filled(rows, cols, 1.0)),
+                (_, Mode::Rational) => {
+                    LalaType::RationalMatrix(Matrix::filled(rows, cols, Field::one()))
+                }
+                (DyadicVerb::Zeros, Mode::Modular(p)) => {
+                    LalaType::ModularMatrix(Matrix::filled(rows, cols, Modular::new(0, p)))
+                }
+                (_, Mode::Modular(p)) => {
+                    LalaType::ModularMatrix(Matrix::filled(rows, cols, Modular::new(1, p)))
+                }
+            });
+        }
+        _ => {}
+    }
+    if let Some(p) = shared_modulus(&leftside, &rightside)? {
+        match (
+            modular_operand(&leftside, p),
+            modular_operand(&rightside, p),
+        ) {
+            (Some(Operand::Matrix(left)), Some(Operand::Matrix(right))) => {
+                let result = field_dyadic(&left, &right, verb)?
+                    .ok_or_else(|| anyhow!("{func} is not available for matrices mod {p}"))?;
+                return Ok(tag_modulus(result, p));
+            }
+            _ if is_modular(&leftside) || is_modular(&rightside) => {
+                return Err(anyhow!("can only call {func} on two matrices mod {p}"))
+            }
+            // neither side is mod p, so mod p mode has nothing to say
+            _ => {}
+        }
+    }
+    if let (LalaType::RationalMatrix(left), LalaType::RationalMatrix(right)) =
+        (&leftside, &rightside)
+    {
+        if let Some(result) = field_dyadic(left, right, verb)? {
+            return Ok(result);
+        }
+    }
+    if is_complex(&leftside) || is_complex(&rightside) {
+        return match (complex_operand(leftside), complex_operand(rightside)) {
+            (Some(Operand::Matrix(left)), Some(Operand::Matrix(right))) => {
+                field_dyadic(&left, &right, verb)?
+                    .ok_or_else(|| anyhow!("{func} is not available for complex matrices"))
+            }
+            _ => Err(anyhow!("can only call {func} on a matrix")),
+        };
+    }
+    let (leftside, rightside) = match (float_operand(leftside), float_operand(rightside)) {
+        (Some(Operand::Matrix(left)), Some(Operand::Matrix(right))) => (left, right),
+        _ => return Err(anyhow!("can only call {func} on a matrix")),
     };
+    if let Some(result) = field_dyadic(&leftside, &rightside, verb)? {
+        return Ok(result);
+    }
     Ok(match verb {
-        DyadicVerb::Dot => match leftside.dot(rightside.clone()) {
-            Ok(m) => LalaType::Matrix(m),
-            Err(e) => return Err(e),
-        },
-        DyadicVerb::Plus => match leftside.combine(rightside, |a, b| a + b) {
-            Ok(result) => LalaType::Matrix(result),
-            Err(e) => return Err(e),
-        },
-        DyadicVerb::Times => match leftside.combine(rightside, |a, b| a * b) {
-            Ok(result) => LalaType::Matrix(result),
-            Err(e) => return Err(e),
+        DyadicVerb::LeastSquares => {
+            let (x, residual) = leftside.lstsq(&rightside)?;
+            LalaType::Tuple(vec![LalaType::Matrix(x), LalaType::Matrix(residual)])
+        }
+        _ => unreachable!("{func} is handled above"),
+    })
+}
+
+/// A matrix dimension, which has to be given as a non-negative integer.
+fn dimension(value: &LalaType, func: &str) -> Result<usize, Error> {
+    match value {
+        LalaType::Integer(i) if *i >= 0 => Ok(*i as usize),
+        _ => Err(anyhow!(
+            "{func} needs non-negative integer sizes, got {value}"
+        )),
+    }
+}
+
+/// A scalar or matrix argument of an arithmetic verb, over one field.
+enum Operand<T> {
+    Scalar(T),
+    Matrix(Matrix<T>),
+}
+
+/// Any number or matrix, rationals rounded to the nearest float.
+fn float_operand(value: LalaType) -> Option<Operand<f64>> {
+    Some(match value {
+        LalaType::Integer(i) => Operand::Scalar(i as f64),
+        LalaType::Double(d) => Operand::Scalar(d),
+        LalaType::Rational(q) => Operand::Scalar(q.to_f64()?),
+        LalaType::Matrix(m) => Operand::Matrix(m),
+        LalaType::RationalMatrix(m) => Operand::Matrix(float_matrix(&m)),
+        _ => return None,
+    })
+}
+
+/// Exact numbers and matrices only, anything already in floating point
+/// gives `None`.
+fn rational_operand(value: &LalaType) -> Option<Operand<BigRational>> {
+    Some(match value {
+        LalaType::Integer(i) => Operand::Scalar(BigRational::from_integer((*i).into())),
+        LalaType::Rational(q) => Operand::Scalar(q.clone()),
+        LalaType::RationalMatrix(m) => Operand::Matrix(m.clone()),
+        _ => return None,
+    })
+}
+
+/// Any number or matrix, real ones given a zero imaginary part.
+fn complex_operand(value: LalaType) -> Option<Operand<Complex64>> {
+    Some(match value {
+        LalaType::Complex(z) => Operand::Scalar(z),
+        LalaType::ComplexMatrix(m) => Operand::Matrix(m),
+        real => match float_operand(real)? {
+            Operand::Scalar(x) => Operand::Scalar(Complex64::new(x, 0.0)),
+            Operand::Matrix(m) => Operand::Matrix(m.map(|&x| Complex64::new(x, 0.0))),
         },
     })
 }
 
+/// Any exact number or matrix, or a float one with whole entries, reduced
+/// mod `p`. A `p` of 0 leaves integers as they are, for exponents.
+fn modular_operand(value: &LalaType, p: i64) -> Option<Operand<Modular>> {
+    let reduce = |x: &Modular| match p {
+        0 => *x,
+        _ => Modular::new(x.value, p),
+    };
+    let exact = |q: &BigRational| match p {
+        0 => Modular::from_rational(q),
+        _ => Modular::from_ratio(q, p),
+    };
+    let whole = |d: &f64| Modular::from_double(*d).map(|x| x.tagged(p));
+    Some(match value {
+        LalaType::Integer(i) => Operand::Scalar(Modular::new((*i).into(), p)),
+        LalaType::Double(d) => Operand::Scalar(whole(d)?),
+        LalaType::Rational(q) => Operand::Scalar(exact(q)?),
+        LalaType::Modular(x) => Operand::Scalar(reduce(x)),
+        LalaType::Matrix(m) => Operand::Matrix(try_map(m, whole)?),
+        LalaType::RationalMatrix(m) => Operand::Matrix(try_map(m, exact)?),
+        LalaType::ModularMatrix(m) => Operand::Matrix(m.map(reduce)),
+        _ => return None,
+    })
+}
+
+/// `m` with every entry converted by `f`, `None` if any entry fails.
+fn try_map<T, U>(m: &Matrix<T>, f: impl Fn(&T) -> Option<U>) -> Option<Matrix<U>> {
+    Some(Matrix {
+        rows: m.rows,
+        cols: m.cols,
+        data: m.data.iter().map(f).collect::<Option<_>>()?,
+    })
+}
+
+fn matrix_modulus(m: &Matrix<Modular>) -> i64 {
+    m.data.iter().map(|x| x.modulus).max().unwrap_or(0)
+}
+
+fn modulus_of(value: &LalaType) -> Option<i64> {
+    match value {
+        LalaType::Modular(x) => Some(x.modulus),
+        LalaType::ModularMatrix(m) => Some(matrix_modulus(m)),
+        _ => None,
+    }
+}
+
+/// The modulus two operands are combined under: theirs if either has one,
+/// otherwise the one set by `/mode mod`.
+fn shared_modulus(lhs: &LalaType, rhs: &LalaType) -> Result<Option<i64>, Error> {
+    match (modulus_of(lhs), modulus_of(rhs)) {
+        (Some(p), Some(q)) if p != q => Err(anyhow!(
+            "cannot mix values mod {p} with values mod {q}, reduce one with mod"
+        )),
+        (Some(p), _) | (_, Some(p)) => Ok(Some(p)),
+        _ => Ok(match mode() {
+            Mode::Modular(p) => Some(p),
+            _ => None,
+        }),
+    }
+}
+
+/// Gives results of elimination their modulus back, since constants like
+/// the ones in an identity matrix are made without one.
+fn tag_modulus(value: LalaType, p: i64) -> LalaType {
+    match value {
+        LalaType::Modular(x) => LalaType::Modular(x.tagged(p)),
+        LalaType::ModularMatrix(m) => LalaType::ModularMatrix(m.map(|x| x.tagged(p))),
+        LalaType::Tuple(values) => {
+            LalaType::Tuple(values.into_iter().map(|v| tag_modulus(v, p)).collect())
+        }
+        other => other,
+    }
+}
+
+/// A modulus given by the user, which has to be a prime for GF(p) to be a
+/// field.
+fn prime_modulus(p: i64) -> Result<i64, Error> {
+    if !is_prime(p) || p > MAX_MODULUS {
+        return Err(anyhow!("the modulus has to be a prime below 2^31, got {p}"));
+    }
+    Ok(p)
+}
+
+fn is_modular(value: &LalaType) -> bool {
+    matches!(value, LalaType::Modular(_) | LalaType::ModularMatrix(_))
+}
+
+fn is_complex(value: &LalaType) -> bool {
+    matches!(value, LalaType::Complex(_) | LalaType::ComplexMatrix(_))
+}
+
+fn is_exact(value: &LalaType) -> bool {
+    matches!(value, LalaType::Rational(_) | LalaType::RationalMatrix(_))
+}
+
+/// Integers stay integers as long as the result is exact and in range,
+/// otherwise the operation is redone in floating point.
+fn integer_arithmetic<'a>(verb: &DyadicVerb, a: i32, b: i32) -> Option<LalaType<'a>> {
+    let exact = match verb {
+        DyadicVerb::Add => a.checked_add(b),
+        DyadicVerb::Subtract => a.checked_sub(b),
+        DyadicVerb::Multiply => a.checked_mul(b),
+        DyadicVerb::Divide => a.checked_rem(b).filter(|r| *r == 0).and(a.checked_div(b)),
+        DyadicVerb::Power => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+        _ => None,
+    };
+    exact.map(LalaType::Integer)
+}
+
+/// `+ - * / ^` on any mix of scalars and matrices. Scalars are broadcast
+/// against every element of a matrix, two matrices are combined elementwise.
+/// Rationals stay exact unless a float is involved, and in rational mode so
+/// do integers that don't divide evenly. Anything mod p, or everything in
+/// mod p mode, is reduced mod p.
+fn arithmetic<'a>(
+    verb: &DyadicVerb,
+    lhs: LalaType<'a>,
+    rhs: LalaType<'a>,
+) -> Result<LalaType<'a>, Error> {
+    if let Some(p) = shared_modulus(&lhs, &rhs)? {
+        // exponents count repetitions, so they are not reduced
+        let exponent_modulus = if *verb == DyadicVerb::Power { 0 } else { p };
+        match (
+            modular_operand(&lhs, p),
+            modular_operand(&rhs, exponent_modulus),
+        ) {
+            (Some(a), Some(b)) => return Ok(tag_modulus(broadcast(verb, a, b)?, p)),
+            _ if is_modular(&lhs) || is_modular(&rhs) => {
+                return Err(anyhow!("cannot call {verb} on {lhs} and {rhs} mod {p}"))
+            }
+            _ => {}
+        }
+    }
+    if let (LalaType::Integer(a), LalaType::Integer(b)) = (&lhs, &rhs) {
+        if let Some(exact) = integer_arithmetic(verb, *a, *b) {
+            return Ok(exact);
+        }
+    }
+    if is_complex(&lhs) || is_complex(&rhs) {
+        return match (complex_operand(lhs), complex_operand(rhs)) {
+            (Some(a), Some(b)) => broadcast(verb, a, b),
+            _ => Err(anyhow!("can only call {verb} on scalars and matrices")),
+        };
+    }
+    if mode() == Mode::Rational || is_exact(&lhs) || is_exact(&rhs) {
+        if let (Some(a), Some(b)) = (rational_operand(&lhs), rational_operand(&rhs)) {
+            return broadcast(verb, a, b);
+        }
+    }
+    match (float_operand(lhs), float_operand(rhs)) {
+        (Some(a), Some(b)) => broadcast(verb, a, b),
+        _ => Err(anyhow!("can only call {verb} on scalars and matrices")),
+    }
+}
+
+fn broadcast<'a, T: Field>(
+    verb: &DyadicVerb,
+    lhs: Operand<T>,
+    rhs: Operand<T>,
+) -> Result<LalaType<'a>, Error>
+where
+    LalaType<'a>: From<Matrix<T>> + From<T>,
+{
+    let op = |a: T, b: T| -> Result<T, Error> {
+        Ok(match verb {
+            DyadicVerb::Add => a + b,
+            DyadicVerb::Subtract => a - b,
+            DyadicVerb::Multiply => a * b,
+            DyadicVerb::Divide if b.is_zero() => return Err(anyhow!("division by zero")),
+            DyadicVerb::Divide => a / b,
+            _ => a
+                .power(&b)
+                .ok_or_else(|| anyhow!("{a} ^ {b} has no exact value, try /mode float"))?,
+        })
+    };
+    let (rows, cols, data) = match (lhs, rhs) {
+        (Operand::Scalar(a), Operand::Scalar(b)) => return Ok(op(a, b)?.into()),
+        (Operand::Matrix(a), Operand::Matrix(b)) => {
+            if a.rows != b.rows || a.cols != b.cols {
+                return Err(anyhow!("Matrices must be of the same size."));
+            }
+            let data = a.data.into_iter().zip(b.data).map(|(x, y)| op(x, y));
+            (a.rows, a.cols, data.collect::<Result<_, _>>()?)
+        }
+        (Operand::Matrix(m), Operand::Scalar(k)) => {
+            let data = m.data.into_iter().map(|x| op(x, k.clone()));
+            (m.rows, m.cols, data.collect::<Result<_, _>>()?)
+        }
+        (Operand::Scalar(k), Operand::Matrix(m)) => {
+            let data = m.data.into_iter().map(|x| op(k.clone(), x));
+            (m.rows, m.cols, data.collect::<Result<_, _>>()?)
+        }
+    };
+    Ok(Matrix { rows, cols, data }.into())
+}
+
+fn scale<'a>(lhs: LalaType<'a>, rhs: LalaType<'a>) -> Result<LalaType<'a>, Error> {
+    match (shape(&lhs), shape(&rhs)) {
+        (Some(_), None) | (None, Some(_)) => arithmetic(&DyadicVerb::Multiply, lhs, rhs),
+        _ => Err(anyhow!(
+            "scalar multiplication needs one scalar and one matrix"
+        )),
+    }
+}
+
 fn eval_assignment<'a, 'b>(
     ident: &'a String,
-    expr: &'b Box<AstNode<'b>>,
+    expr: &'b AstNode<'b>,
     env: &mut HashMap<String, LalaType<'a>>,
 ) -> Result<(), Error>
 where
     'b: 'a,
 {
-    match expr.deref() {
-        AstNode::Integer(scalar) => match env.insert(ident.to_string(), LalaType::Integer(*scalar))
-        {
-            _ => Ok(()),
-        },
-        AstNode::DoublePrecisionFloat(scalar) => {
-            match env.insert(ident.to_string(), LalaType::Double(*scalar)) {
-                _ => Ok(()),
-            }
-        }
+    match expr {
         AstNode::Ident(rhs_ident) => {
             let val = match env.get(rhs_ident) {
                 Some(v) => v,
                 None => return Err(anyhow!("{rhs_ident} referenced before definition.")),
             };
-            match env.insert(ident.to_string(), val.clone()) {
-                _ => Ok(()),
-            }
-        }
-        AstNode::Matrix(v) => {
-            let mat = construct_matrix(v)?;
-            match env.insert(ident.to_string(), LalaType::Matrix(mat)) {
-                _ => Ok(()),
-            }
+            env.insert(ident.to_string(), val.clone());
+            Ok(())
         }
         AstNode::MonadicOp { verb, expr } => {
             let result = eval_monadic_op(expr, env, verb)?;
-            match env.insert(ident.to_string(), result) {
-                _ => Ok(()),
-            }
+            env.insert(ident.to_string(), result);
+            Ok(())
         }
         AstNode::DyadicOp { verb, lhs, rhs } => {
             let result = eval_dyadic_op(lhs, rhs, env, verb)?;
-            match env.insert(ident.to_string(), result) {
-                _ => Ok(()),
-            }
-        }        
+            env.insert(ident.to_string(), result);
+            Ok(())
+        }
         AstNode::App((name, params)) => {
-            let result = match interp_app(name, params, env) {
-                Ok(val) => val,
-                Err(e) => return Err(e),
-            };
-            match env.insert(ident.to_string(), result) {
-                _ => Ok(()),
-            }
+            let result = interp_app(name, params, env)?;
+            env.insert(ident.to_string(), result);
+            Ok(())
+        }
+        AstNode::Integer(_)
+        | AstNode::DoublePrecisionFloat(_)
+        | AstNode::Rational(_)
+        | AstNode::Complex(_)
+        | AstNode::Matrix(_)
+        | AstNode::Tuple(_)
+        | AstNode::Index { .. }
+        | AstNode::Terms(_) => {
+            let result = eval_expr(env, expr, "assignment")?;
+            env.insert(ident.to_string(), result);
+            Ok(())
         }
         _ => Err(anyhow!("interpreter error!")),
     }
 }
 
+fn eval_destructure<'a, 'b>(
+    idents: &[String],
+    expr: &'b AstNode<'b>,
+    env: &mut HashMap<String, LalaType<'a>>,
+) -> Result<(), Error>
+where
+    'b: 'a,
+{
+    let values = match eval_expr(env, expr, "destructuring")? {
+        LalaType::Tuple(values) => values,
+        _ => {
+            return Err(anyhow!(
+                "can only unpack a tuple into ({})",
+                idents.join(" ")
+            ))
+        }
+    };
+    if values.len() != idents.len() {
+        return Err(anyhow!(
+            "cannot unpack a tuple of {} values into {} names",
+            values.len(),
+            idents.len()
+        ));
+    }
+    for (ident, value) in idents.iter().zip(values) {
+        env.insert(ident.to_string(), value);
+    }
+    Ok(())
+}
+
+/// Overwrites part of the matrix bound to `ident`. A scalar fills the whole
+/// block, a matrix has to match its shape.
+fn eval_index_assignment<'a, 'b>(
+    ident: &'a String,
+    rows: &'b Slice<'b>,
+    cols: &'b Slice<'b>,
+    expr: &'b AstNode<'b>,
+    env: &mut HashMap<String, LalaType<'a>>,
+) -> Result<(), Error>
+where
+    'b: 'a,
+{
+    let target = get_value(env, ident)?;
+    let (nrows, ncols) = shape(&target)
+        .ok_or_else(|| anyhow!("can only index into a matrix, {ident} is not one"))?;
+    let (row_range, _) = eval_slice(env, rows, nrows, "row")?;
+    let (col_range, _) = eval_slice(env, cols, ncols, "column")?;
+    let value = eval_expr(env, expr, "assignment")?;
+    let cannot = anyhow!("cannot assign {value} into {ident}");
+    // only for its error when the two are mod different primes
+    shared_modulus(&target, &value)?;
+    let updated = match (target, rational_operand(&value)) {
+        (LalaType::ModularMatrix(mut m), _) => {
+            let p = matrix_modulus(&m);
+            let block = modular_operand(&value, p).ok_or(cannot)?;
+            assign_block(&mut m, row_range, col_range, block)?;
+            LalaType::ModularMatrix(m)
+        }
+        (LalaType::RationalMatrix(mut m), Some(block)) => {
+            assign_block(&mut m, row_range, col_range, block)?;
+            LalaType::RationalMatrix(m)
+        }
+        (target, _) if is_complex(&target) || is_complex(&value) => {
+            let mut m = match complex_operand(target) {
+                Some(Operand::Matrix(m)) => m,
+                _ => unreachable!("{ident} has a shape so it is a matrix"),
+            };
+            let block = complex_operand(value).ok_or(cannot)?;
+            assign_block(&mut m, row_range, col_range, block)?;
+            LalaType::ComplexMatrix(m)
+        }
+        // anything inexact turns the whole matrix into floats
+        (target, _) => {
+            let mut m = match float_operand(target) {
+                Some(Operand::Matrix(m)) => m,
+                _ => unreachable!("{ident} has a shape so it is a matrix"),
+            };
+            let block = float_operand(value).ok_or(cannot)?;
+            assign_block(&mut m, row_range, col_range, block)?;
+            LalaType::Matrix(m)
+        }
+    };
+    env.insert(ident.to_string(), updated);
+    Ok(())
+}
+
+fn assign_block<T: Field>(
+    m: &mut Matrix<T>,
+    rows: Range<usize>,
+    cols: Range<usize>,
+    value: Operand<T>,
+) -> Result<(), Error> {
+    let block = match value {
+        Operand::Matrix(block) => block,
+        Operand::Scalar(k) => Matrix::filled(rows.len(), cols.len(), k),
+    };
+    m.set_submatrix(rows, cols, &block)
+}
+
 fn interp_fun<'a>(
     name: &String,
-    params: &Vec<AstNode<'a>>,
-    body: &Vec<AstNode<'a>>,
+    params: &'a [AstNode<'a>],
+    body: &'a [AstNode<'a>],
     env: &mut HashMap<String, LalaType<'a>>,
-) -> () {
-    match env.insert(
+) {
+    env.insert(
         name.to_string(),
-        LalaType::Fun((name.to_string(), params.to_vec(), body.to_vec())),
-    ) {
-        _ => (),
-    }
+        LalaType::Fun((name.to_string(), params, body)),
+    );
 }
 
 fn interp_app<'a, 'b>(
@@ -182,8 +901,8 @@ fn interp_app<'a, 'b>(
 where
     'a: 'b,
 {
-    let (_, aliases, body_o) = match env.get(name) {
-        Some(LalaType::Fun((n, a, b))) => (n, a, b),
+    let (aliases, body) = match env.get(name) {
+        Some(LalaType::Fun((_, a, b))) => (*a, *b),
         _ => {
             return Err(anyhow!("Function {name} referenced before definition"));
         }
@@ -211,13 +930,9 @@ where
 
         // evaluate the provided parameter
         let provided = match provided_node {
-            AstNode::Integer(int) => LalaType::Integer(*int),
-            AstNode::DoublePrecisionFloat(d) => LalaType::Double(*d),
-            AstNode::MonadicOp { verb, expr } => {
-                eval_monadic_op(&expr, &mut function_scope, &verb)?
-            }
+            AstNode::MonadicOp { verb, expr } => eval_monadic_op(expr, &mut function_scope, verb)?,
             AstNode::DyadicOp { verb, lhs, rhs } => {
-                eval_dyadic_op(&lhs, &rhs, &mut function_scope, &verb)?
+                eval_dyadic_op(lhs, rhs, &mut function_scope, verb)?
             }
             AstNode::Ident(i) => match function_scope.get(i) {
                 Some(val) => val.clone(),
@@ -225,13 +940,12 @@ where
                     return Err(anyhow!("identifer {i} referenced before definition"));
                 }
             },
-            AstNode::Matrix(m) => {
-                if let Ok(mat) = construct_matrix(&m) {
-                    LalaType::Matrix(mat)
-                } else {
-                    return Err(anyhow!("problem passing matrix to function..."));
-                }
-            }
+            AstNode::Integer(_)
+            | AstNode::DoublePrecisionFloat(_)
+            | AstNode::Rational(_)
+            | AstNode::Complex(_)
+            | AstNode::Matrix(_)
+            | AstNode::Index { .. } => eval_expr(&mut function_scope, provided_node, "argument")?,
             AstNode::App((func_name, func_params)) => {
                 let temp =
                     if let Ok(intermediate) = interp_app(func_name, func_params, &function_scope) {
@@ -255,7 +969,6 @@ where
     // now that the parameter values have been assigned, we just need to interpret the
     // body of the function and return the result of the last expression
 
-    let body = body_o.to_owned().leak();
     // function body can only contain assignment of variables and functions
     for expr in body[0..body.len() - 1].iter() {
         match expr {
@@ -267,6 +980,15 @@ where
                     }
                 }
             }
+            AstNode::Destructure { idents, expr } => {
+                eval_destructure(idents, expr, &mut function_scope)?
+            }
+            AstNode::IndexAssignment {
+                ident,
+                rows,
+                cols,
+                expr,
+            } => eval_index_assignment(ident, rows, cols, expr, &mut function_scope)?,
             AstNode::Fun((name, params, body)) => {
                 interp_fun(name, params, body, &mut function_scope)
             }
@@ -279,30 +1001,232 @@ where
     }
 
     let last_expr = match body.last() {
-        Some(res) => res.to_owned(),
+        Some(res) => res,
         None => {
             return Err(anyhow!("empty function body somehow!"));
         }
     };
 
     let final_result = match last_expr {
-        // FUNCTIONS MUST END WITH IDENTIFIERS AS THE RETURN VALUE
-        AstNode::Ident(id) => match function_scope.get(&id) {
+        // FUNCTIONS MUST END WITH IDENTIFIERS (OR A TUPLE OF THEM) AS THE RETURN VALUE
+        AstNode::Ident(id) => match function_scope.get(id) {
             Some(val) => val.to_owned(),
-            None => todo!(),
+            None => return Err(anyhow!("{id} referenced before definition.")),
         },
+        AstNode::Tuple(_) => eval_expr(&mut function_scope, last_expr, "return")?,
         _ => {
-            return Err(anyhow!("return statement must only be an identifier"));
+            return Err(anyhow!(
+                "return statement must only be an identifier or a tuple"
+            ));
         }
     };
 
     Ok(final_result)
 }
 
+const HELP: &str = "\
+/link <file.lala> ...  run other scripts, keeping their bindings
+/env                   list the names bound in the environment
+/dbg                   dump every binding in the environment
+/clear                 remove every binding and go back to float mode
+/seed <n>              reseed the generator behind rand
+/mode [float|rational] show or switch how numbers are computed
+/mode mod <p>          compute everything mod the prime p
+/format [plain|latex|markdown|full]
+                       show or switch how results are printed
+/help                  show this message";
+
+/// Number system literals are read in, switched with `/mode`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Mode {
+    Float,
+    Rational,
+    Modular(i64),
+}
+
+thread_local! {
+    /// Generator behind `rand`. Starts from entropy, `/seed` makes runs
+    /// reproducible.
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+
+    static MODE: Cell<Mode> = const { Cell::new(Mode::Float) };
+}
+
+fn mode() -> Mode {
+    MODE.with(|mode| mode.get())
+}
+
+fn describe(value: &LalaType) -> String {
+    match value {
+        LalaType::Integer(i) => format!("integer {i}"),
+        LalaType::Double(d) => format!("double {d}"),
+        LalaType::Rational(q) => format!("rational {q}"),
+        LalaType::Matrix(m) => format!("matrix {}x{}", m.rows, m.cols),
+        LalaType::RationalMatrix(m) => format!("rational matrix {}x{}", m.rows, m.cols),
+        LalaType::Complex(z) => format!("complex {z}"),
+        LalaType::ComplexMatrix(m) => format!("complex matrix {}x{}", m.rows, m.cols),
+        LalaType::Modular(x) => format!("integer {x} mod {}", x.modulus),
+        LalaType::ModularMatrix(m) => {
+            format!("matrix {}x{} mod {}", m.rows, m.cols, matrix_modulus(m))
+        }
+        LalaType::Steps { steps, .. } => format!("rref trace of {} steps", steps.len()),
+        LalaType::Tuple(values) => format!("tuple of {} values", values.len()),
+        LalaType::Fun((_, params, _)) => format!("fun of {} params", params.len()),
+    }
+}
+
+fn eval_command<'a>(
+    cmd: &str,
+    params: &[&str],
+    env: &mut HashMap<String, LalaType<'a>>,
+    session: &'a Session<'a>,
+) -> Result<String, Error> {
+    let mut names: Vec<&String> = env.keys().collect();
+    names.sort();
+    match cmd {
+        "link" => {
+            if params.is_empty() {
+                return Err(anyhow!("/link needs at least one file to link"));
+            }
+            for path in params {
+                let file = session.resolve(path);
+                let source = std::fs::read_to_string(&file)
+                    .map_err(|e| anyhow!("could not link {path}: {e}"))?;
+                let ast = session.parse(&source)?;
+                session.enter(&file)?;
+                // a /mode in the linked script stays in that script
+                let saved = mode();
+                let linked = interp(ast, Some(env), false, session);
+                MODE.with(|mode| mode.set(saved));
+                session.leave();
+                linked?;
+            }
+            Ok(format!("linked {}", params.join(" ")))
+        }
+        "env" => Ok(names
+            .iter()
+            .map(|name| format!("{name}: {}", describe(&env[*name])))
+            .collect::<Vec<String>>()
+            .join("\n")),
+        "dbg" => Ok(names
+            .iter()
+            .map(|name| format!("{name} = {:?}", env[*name]))
+            .collect::<Vec<String>>()
+            .join("\n")),
+        "clear" => {
+            env.clear();
+            MODE.with(|mode| mode.set(Mode::Float));
+            Ok(String::from("environment cleared, float mode"))
+        }
+        "seed" => {
+            let seed: u64 = match params {
+                [seed] => seed
+                    .parse()
+                    .map_err(|_| anyhow!("/seed needs a non-negative integer, got {seed}"))?,
+                _ => return Err(anyhow!("/seed takes exactly one seed")),
+            };
+            RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+            Ok(format!("rand seeded with {seed}"))
+        }
+        "mode" => {
+            let new_mode = match params {
+                [] => None,
+                ["float"] => Some(Mode::Float),
+                ["rational"] => Some(Mode::Rational),
+                ["mod", p] => {
+                    let p = p
+                        .parse()
+                        .map_err(|_| anyhow!("/mode mod needs a prime, got {p}"))?;
+                    Some(Mode::Modular(prime_modulus(p)?))
+                }
+                _ => return Err(anyhow!("/mode takes float, rational or mod <p>")),
+            };
+            if let Some(new_mode) = new_mode {
+                MODE.with(|mode| mode.set(new_mode));
+            }
+            Ok(match mode() {
+                Mode::Modular(p) => format!("mod {p} mode"),
+                other => format!("{other:?} mode").to_lowercase(),
+            })
+        }
+        "format" => {
+            let new_format = match params {
+                [] => None,
+                [name] => Some(
+                    Format::from_name(name)
+                        .ok_or_else(|| anyhow!("/format takes plain, latex, markdown or full"))?,
+                ),
+                _ => return Err(anyhow!("/format takes plain, latex, markdown or full")),
+            };
+            if let Some(new_format) = new_format {
+                FORMAT.with(|format| format.set(new_format));
+            }
+            Ok(format!("{:?} format", output_format()).to_lowercase())
+        }
+        "help" => Ok(String::from(HELP)),
+        unknown => Err(anyhow!("unknown command /{unknown}, try /help")),
+    }
+}
+
+/// Keeps the source and syntax tree of everything run in a session alive as
+/// long as the session, since bindings such as functions borrow from them.
+/// Dropped with the session instead of leaked line by line.
+///
+/// Also tracks the scripts being run, innermost last, so `/link` paths
+/// resolve against the file that names them and link cycles are caught.
+#[derive(Default)]
+pub struct Session<'a> {
+    sources: Arena<String>,
+    #[allow(clippy::vec_box)] // the shape `parser::parse` hands back
+    asts: Arena<Vec<Box<AstNode<'a>>>>,
+    scripts: RefCell<Vec<PathBuf>>,
+}
+
+impl<'a> Session<'a> {
+    pub fn parse(&'a self, source: &str) -> Result<&'a [Box<AstNode<'a>>], Error> {
+        let source = self.sources.alloc(source.to_string());
+        Ok(self.asts.alloc(parser::parse(source)?))
+    }
+
+    /// Marks `script` as the file now running, until the matching `leave`.
+    /// Fails if it is already running, since linking it again would recurse
+    /// without end.
+    pub fn enter(&self, script: &Path) -> Result<(), Error> {
+        let script = script
+            .canonicalize()
+            .map_err(|e| anyhow!("could not open {}: {e}", script.display()))?;
+        let mut scripts = self.scripts.borrow_mut();
+        if scripts.contains(&script) {
+            return Err(anyhow!("{} links back to itself", script.display()));
+        }
+        scripts.push(script);
+        Ok(())
+    }
+
+    pub fn leave(&self) {
+        self.scripts.borrow_mut().pop();
+    }
+
+    /// `path` relative to the running script, or to the working directory
+    /// when no script is running, as in the repl.
+    fn resolve(&self, path: &str) -> PathBuf {
+        match self
+            .scripts
+            .borrow()
+            .last()
+            .and_then(|script| script.parent())
+        {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+}
+
 pub fn interp<'a>(
     ast: &'a [Box<AstNode<'_>>],
     map: Option<&mut HashMap<String, LalaType<'a>>>,
     tcp: bool,
+    session: &'a Session<'a>,
 ) -> Result<String, Error> {
     let mut binding = HashMap::new();
     #[allow(unused_mut)]
@@ -314,9 +1238,9 @@ pub fn interp<'a>(
     let mut result = String::new();
 
     for node in ast {
-        let _ = match node.deref() {
+        match node.deref() {
             AstNode::Assignment { ident, expr } => {
-                let _ = eval_assignment(ident, expr, env)?;
+                eval_assignment(ident, expr, env)?;
                 result = if tcp {
                     format!("{}", env.get(ident).unwrap())
                 } else {
@@ -338,6 +1262,42 @@ pub fn interp<'a>(
                 };
                 result = format!("{}", value);
             }
+            AstNode::Destructure { idents, expr } => {
+                eval_destructure(idents, expr, env)?;
+                result = if tcp {
+                    idents
+                        .iter()
+                        .map(|ident| format!("{}", env[ident]))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                } else {
+                    result
+                };
+            }
+            AstNode::IndexAssignment {
+                ident,
+                rows,
+                cols,
+                expr,
+            } => {
+                eval_index_assignment(ident, rows, cols, expr, env)?;
+                result = if tcp {
+                    format!("{}", env[ident])
+                } else {
+                    result
+                };
+            }
+            AstNode::Integer(_)
+            | AstNode::DoublePrecisionFloat(_)
+            | AstNode::Rational(_)
+            | AstNode::Complex(_)
+            | AstNode::Matrix(_)
+            | AstNode::Tuple(_)
+            | AstNode::Index { .. }
+            | AstNode::Terms(_) => {
+                let res = eval_expr(env, node, "expression")?;
+                result = format!("{}", res);
+            }
             AstNode::Fun((name, params, body)) => {
                 interp_fun(name, params, body, env);
                 result = format!("fun {name} added to env");
@@ -348,9 +1308,86 @@ pub fn interp<'a>(
                     Err(e) => return Err(e),
                 }
             }
-            bad_line => return Ok(format!("Invalid line: {:?}", bad_line)),
+            AstNode::Command((cmd, params)) => {
+                result = eval_command(cmd, params, env, session)?;
+            }
         };
     }
 
     Ok(result)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> Result<String, Error> {
+        let session = Session::default();
+        let mut env = HashMap::new();
+        let ast = session.parse(source)?;
+        interp(ast, Some(&mut env), false, &session)
+    }
+
+    #[test]
+    fn link_keeps_the_callers_mode() {
+        let linked = std::env::temp_dir().join(format!("lala-mode-{}.lala", std::process::id()));
+        std::fs::write(&linked, "/mode rational\nlet third = 1 / 3\n").unwrap();
+        let source = format!("/link {}\nlet x = 1 / 3", linked.display());
+        let session = Session::default();
+        let mut env = HashMap::new();
+        let result = interp(
+            session.parse(&source).unwrap(),
+            Some(&mut env),
+            false,
+            &session,
+        );
+        std::fs::remove_file(&linked).unwrap();
+        result.unwrap();
+        assert!(matches!(env["third"], LalaType::Rational(_)));
+        assert!(matches!(env["x"], LalaType::Double(_)));
+        assert_eq!(mode(), Mode::Float);
+
+        assert_eq!(
+            run("/mode rational\n/clear\nlet x = 1 / 4\nx")
+                .unwrap()
+                .trim(),
+            "0.25"
+        );
+    }
+
+    #[test]
+    fn rational_mode() {
+        let result = run("/mode rational\nlet a = 1 2; 3 4\nlet x = det a\nx").unwrap();
+        assert_eq!(result.trim(), "-2");
+        let result = run("/mode rational\nlet a = 0.1 1/3\nlet b = a ! 3\nb").unwrap();
+        assert_eq!(result.trim(), "[3/10 1]");
+        MODE.with(|mode| mode.set(Mode::Float));
+    }
+
+    #[test]
+    fn modular_matrices() {
+        // entries without a modulus, like those of an identity, take the
+        // matrix's
+        let m = Matrix {
+            rows: 1,
+            cols: 3,
+            data: vec![Modular::one(), Modular::new(3, 7), Modular::zero()],
+        };
+        assert_eq!(matrix_modulus(&m), 7);
+        assert_eq!(matrix_modulus(&Matrix::<Modular>::identity(2)), 0);
+
+        let result = run("let a = mod 7 (2 1; 1 3)\nlet b = (? a) @ a\nb").unwrap();
+        assert_eq!(result.trim(), "[1 0]\n[0 1]");
+        let result = run("let a = mod 2 (1 1; 1 -1)\nlet r = # a\nr").unwrap();
+        assert_eq!(result.trim(), "1");
+
+        let mixed = "let a = mod 5 (1 2; 3 4)\nlet b = mod 7 (1 0; 0 1)\n";
+        for op in ["a + b", "a @ b", "(mod 7 3) * a"] {
+            let error = run(&format!("{mixed}{op}")).unwrap_err();
+            assert!(
+                error.to_string().contains("cannot mix values mod"),
+                "{op}: {error}"
+            );
+        }
+    }
+}
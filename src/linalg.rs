@@ -1,20 +1,24 @@
+use super::field::{Field, FloatField};
 use anyhow::{anyhow, Error};
-use std::ops::{Index, IndexMut};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+use rand::Rng;
+use std::ops::{Index, IndexMut, Range};
 
+/// Dense row-major matrix. Entries are `f64` unless another `Field` is
+/// asked for; everything built on elimination works for any field, while the
+/// orthogonal factorizations further down are floating point only.
 #[derive(Debug, PartialEq, Clone)]
-pub struct Matrix {
+pub struct Matrix<T = f64> {
     pub rows: usize,
     pub cols: usize,
-    pub data: Vec<f64>,
+    pub data: Vec<T>,
 }
 
-impl Matrix {
+impl<T: Field> Matrix<T> {
     pub fn new(rows: usize, cols: usize) -> Self {
-        Self {
-            rows,
-            cols,
-            data: vec![0.0; rows * cols],
-        }
+        Self::filled(rows, cols, T::zero())
     }
 
     pub fn dot(&self, b: Self) -> Result<Self, Error> {
@@ -30,9 +34,9 @@ impl Matrix {
         let mut dp = Self::new(self.rows, b.cols);
         for i in 0..self.rows {
             for j in 0..b.cols {
-                let mut sum = 0.0;
+                let mut sum = T::zero();
                 for k in 0..b.rows {
-                    sum += self[i][k] * b[k][j];
+                    sum = sum + self[i][k].clone() * b[k][j].clone();
                 }
                 dp[i][j] = sum;
             }
@@ -41,156 +45,448 @@ impl Matrix {
     }
 
     pub fn rank(&self) -> i32 {
-        let reduced = self.rref().data;
-        let rows = self.rows;
-        let cols = self.cols;
-        let mut rank = 0;
-        for r in 0..rows {
-            let row = &reduced[r * cols..r * cols + cols];
-            let (prefix, aligned, suffix) = unsafe { row.align_to::<f64>() };
-            if !(prefix.iter().all(|&x| x == 0f64)
-                && suffix.iter().all(|&x| x == 0f64)
-                && aligned.iter().all(|&x| x == 0f64))
-            {
-                rank += 1;
-            }
-        }
-        rank
+        T::rank(self) as i32
     }
 
+    /// Gauss-Jordan elimination with partial pivoting. Works for any shape;
+    /// columns whose remaining entries are all below the tolerance are
+    /// treated as zero and skipped.
     pub fn rref(&self) -> Self {
+        self.rref_traced(|_, _| {})
+    }
+
+    /// `rref` along with every row operation it performed, each paired with
+    /// the matrix right after it.
+    pub fn rref_steps(&self) -> (Self, Vec<(RowOp<T>, Self)>) {
+        let mut steps = Vec::new();
+        // cleaned up like the final result, so the last step shows what rref
+        // does
+        let tol = self.tolerance();
+        let reduced = self.rref_traced(|op, m| {
+            let mut snapshot = m.clone();
+            snapshot.zero_negligible(tol);
+            steps.push((op, snapshot));
+        });
+        (reduced, steps)
+    }
+
+    fn rref_traced(&self, mut record: impl FnMut(RowOp<T>, &Self)) -> Self {
         let mut reduced = self.clone();
-        if reduced[0][0] == 0.0 {
-            reduced.swap_rows(0);
-        }
-        let mut lead: usize = 0;
-        let rows = reduced.rows;
-        while lead < rows {
-            for r in 0..rows {
-                let div = reduced[lead][lead];
-                let mult = reduced[r][lead] / div;
-
-                if r == lead {
-                    reduced[lead]
-                        .iter_mut()
-                        .for_each(|elem| *elem = (*elem) / div);
-                } else {
-                    for c in 0..reduced.cols {
-                        reduced[r][c] -= reduced[lead][c] * mult;
-                    }
+        let tol = reduced.tolerance();
+        let mut pivot_row = 0;
+        for col in 0..reduced.cols {
+            if pivot_row == reduced.rows {
+                break;
+            }
+            let best = reduced.pivot(pivot_row, col);
+            if reduced[best][col].is_negligible(tol) {
+                for r in pivot_row..reduced.rows {
+                    reduced[r][col] = T::zero();
+                }
+                continue;
+            }
+            if best != pivot_row {
+                reduced.swap_rows(pivot_row, best);
+                record(RowOp::Swap(pivot_row, best), &reduced);
+            }
+
+            let div = reduced[pivot_row][col].clone();
+            if div != T::one() {
+                reduced[pivot_row]
+                    .iter_mut()
+                    .for_each(|elem| *elem = elem.clone() / div.clone());
+                record(
+                    RowOp::Scale {
+                        row: pivot_row,
+                        divisor: div,
+                    },
+                    &reduced,
+                );
+            }
+            for r in 0..reduced.rows {
+                let mult = reduced[r][col].clone();
+                if r == pivot_row || mult.is_zero() {
+                    continue;
                 }
+                for c in 0..reduced.cols {
+                    reduced[r][c] =
+                        reduced[r][c].clone() - reduced[pivot_row][c].clone() * mult.clone();
+                }
+                record(
+                    RowOp::Subtract {
+                        target: r,
+                        source: pivot_row,
+                        factor: mult,
+                    },
+                    &reduced,
+                );
             }
-            lead += 1;
+            pivot_row += 1;
         }
-        reduced.correct();
+        reduced.zero_negligible(tol);
         reduced
     }
 
-    pub fn cofactor(&self, expanded_row: usize, j: usize) -> Result<f64, Error> {
-        let mut cut: Vec<Vec<f64>> = Vec::new();
-        for r in 0..self.rows {
-            if r == expanded_row {
-                continue;
+    /// Rank counted from the non-zero rows of the reduced matrix.
+    pub(crate) fn elimination_rank(&self) -> usize {
+        let reduced = self.rref();
+        (0..reduced.rows)
+            .filter(|&r| reduced[r].iter().any(|x| !x.is_zero()))
+            .count()
+    }
+
+    /// Column of the leading entry of each non-zero row of the reduced
+    /// matrix, top to bottom.
+    fn pivot_columns(reduced: &Self) -> Vec<usize> {
+        (0..reduced.rows)
+            .filter_map(|r| reduced[r].iter().position(|x| !x.is_zero()))
+            .collect()
+    }
+
+    /// Basis of the vectors x with A x = 0, one column per free variable.
+    /// Each sets its free variable to 1 and the others to 0, and reads the
+    /// pivot variables off the reduced matrix. Has no columns when A has
+    /// full column rank.
+    pub fn null_space(&self) -> Self {
+        let reduced = self.rref();
+        let pivots = Self::pivot_columns(&reduced);
+        let free: Vec<usize> = (0..self.cols).filter(|c| !pivots.contains(c)).collect();
+        let mut basis = Self::new(self.cols, free.len());
+        for (j, &f) in free.iter().enumerate() {
+            basis[f][j] = T::one();
+            for (r, &p) in pivots.iter().enumerate() {
+                // subtracted from zero rather than negated so zeros stay +0
+                basis[p][j] = T::zero() - reduced[r][f].clone();
+            }
+        }
+        basis
+    }
+
+    /// Basis of the span of A's columns: the columns of A itself where the
+    /// reduced matrix has its pivots.
+    pub fn column_space(&self) -> Self {
+        let pivots = Self::pivot_columns(&self.rref());
+        let mut basis = Self::new(self.rows, pivots.len());
+        for (j, &p) in pivots.iter().enumerate() {
+            for r in 0..self.rows {
+                basis[r][j] = self[r][p].clone();
             }
-            let mut v: Vec<f64> = Vec::new();
+        }
+        basis
+    }
+
+    /// Basis of the span of A's rows, given as columns: the non-zero rows
+    /// of the reduced matrix.
+    pub fn row_space(&self) -> Self {
+        let reduced = self.rref();
+        let rank = Self::pivot_columns(&reduced).len();
+        let mut basis = Self::new(self.cols, rank);
+        for r in 0..rank {
             for c in 0..self.cols {
-                if c == j {
-                    continue;
+                basis[c][r] = reduced[r][c].clone();
+            }
+        }
+        basis
+    }
+
+    /// LU decomposition with partial pivoting. Returns `(L, U, P)` with
+    /// `P A = L U`, where L is unit lower triangular and U is upper
+    /// triangular. Rectangular matrices give an m by k L and a k by n U,
+    /// k being the smaller dimension.
+    pub fn lu(&self) -> (Self, Self, Self) {
+        let factors = self.lu_factor();
+        let k = self.rows.min(self.cols);
+        let mut l = Self::new(self.rows, k);
+        let mut u = Self::new(k, self.cols);
+        let mut p = Self::new(self.rows, self.rows);
+        for r in 0..self.rows {
+            for c in 0..self.cols {
+                match r.cmp(&c) {
+                    std::cmp::Ordering::Greater if c < k => l[r][c] = factors.lu[r][c].clone(),
+                    std::cmp::Ordering::Greater => {}
+                    _ => u[r][c] = factors.lu[r][c].clone(),
+                }
+            }
+            if r < k {
+                l[r][r] = T::one();
+            }
+            p[r][factors.perm[r]] = T::one();
+        }
+        (l, u, p)
+    }
+
+    fn lu_factor(&self) -> LuFactors<T> {
+        let mut lu = self.clone();
+        let mut perm: Vec<usize> = (0..self.rows).collect();
+        let mut sign = T::one();
+        let tol = self.tolerance();
+        for k in 0..self.rows.min(self.cols) {
+            let best = lu.pivot(k, k);
+            if best != k {
+                lu.swap_rows(k, best);
+                perm.swap(k, best);
+                sign = -sign;
+            }
+            let pivot = lu[k][k].clone();
+            for r in k + 1..self.rows {
+                // a negligible pivot means the column is already eliminated
+                let mult = if pivot.is_negligible(tol) {
+                    T::zero()
+                } else {
+                    lu[r][k].clone() / pivot.clone()
+                };
+                lu[r][k] = mult.clone();
+                for c in k + 1..self.cols {
+                    lu[r][c] = lu[r][c].clone() - mult.clone() * lu[k][c].clone();
                 }
-                v.push(self[r][c]);
             }
-            cut.push(v);
         }
-        let flattened = cut.clone().into_iter().flatten().collect();
-        let n_r = cut.len();
-        let n_c = cut[0].len();
-        let minor = (Self {
-            rows: n_r,
-            cols: n_c,
-            data: flattened,
+        LuFactors {
+            lu,
+            perm,
+            sign,
+            tol,
         }
-        .det())?;
-        let base: i32 = -1;
-        Ok(minor * f64::from(base.pow((expanded_row + j) as u32)))
     }
 
-    pub fn det(&self) -> Result<f64, Error> {
+    pub fn det(&self) -> Result<T, Error> {
         if self.rows != self.cols {
             return Err(anyhow!("Determinant requires matrix to be a square"));
         }
-        if self.rows == 2 && self.cols == 2 {
-            Ok(self[0][0] * self[1][1] - self[0][1] * self[1][0])
-        } else {
-            let row: usize = 1;
-            let mut det = 0.0;
+        let factors = self.lu_factor();
+        let det = (0..self.rows).fold(factors.sign, |det, i| det * factors.lu[i][i].clone());
+        Ok(T::tidy_det(det, self.rows))
+    }
+
+    /// Solves A X = B, one column of X for each column of B. How depends on
+    /// the field, see `Field::solve`.
+    pub fn solve(&self, b: &Self) -> Result<Self, Error> {
+        if self.rows != b.rows {
+            return Err(anyhow!(
+                "Dimensions not matched. A has {} rows, B has {} rows.",
+                self.rows,
+                b.rows
+            ));
+        }
+        T::solve(self, b)
+    }
+
+    /// Square, non-singular systems go through LU; anything else is
+    /// classified from the reduced augmented matrix and only succeeds when
+    /// the solution is unique.
+    pub(crate) fn solve_by_elimination(&self, b: &Self) -> Result<Self, Error> {
+        if self.rows == self.cols {
+            let factors = self.lu_factor();
+            if !factors.is_singular() {
+                return Ok(factors.solve(b));
+            }
+        }
 
-            for j in 0..self[row].len() {
-                det += self.cofactor(row, j)? * self[row][j];
+        let reduced = self.hstack(b)?.rref();
+        let mut rank = 0;
+        for r in 0..reduced.rows {
+            let (coeffs, rhs) = reduced[r].split_at(self.cols);
+            if coeffs.iter().any(|x| !x.is_zero()) {
+                rank += 1;
+            } else if rhs.iter().any(|x| !x.is_zero()) {
+                return Err(anyhow!("System is inconsistent, it has no solution."));
             }
-            Ok(det)
         }
+        if rank < self.cols {
+            return Err(anyhow!(
+                "Matrix is singular, the system has infinitely many solutions."
+            ));
+        }
+
+        let mut x = Self::new(self.cols, b.cols);
+        for r in 0..self.cols {
+            x[r].clone_from_slice(&reduced[r][self.cols..]);
+        }
+        Ok(x)
+    }
+
+    /// Copies out the block of rows `rows` and columns `cols`.
+    pub fn submatrix(&self, rows: Range<usize>, cols: Range<usize>) -> Result<Self, Error> {
+        if rows.end > self.rows || cols.end > self.cols {
+            return Err(anyhow!(
+                "Block [{}:{}, {}:{}] is out of bounds for a {} by {} matrix.",
+                rows.start,
+                rows.end,
+                cols.start,
+                cols.end,
+                self.rows,
+                self.cols
+            ));
+        }
+        let mut sub = Self::new(rows.len(), cols.len());
+        for (i, r) in rows.enumerate() {
+            sub[i].clone_from_slice(&self[r][cols.clone()]);
+        }
+        Ok(sub)
+    }
+
+    /// Writes `block` over rows `rows` and columns `cols`.
+    pub fn set_submatrix(
+        &mut self,
+        rows: Range<usize>,
+        cols: Range<usize>,
+        block: &Self,
+    ) -> Result<(), Error> {
+        if rows.end > self.rows || cols.end > self.cols {
+            return Err(anyhow!(
+                "Block [{}:{}, {}:{}] is out of bounds for a {} by {} matrix.",
+                rows.start,
+                rows.end,
+                cols.start,
+                cols.end,
+                self.rows,
+                self.cols
+            ));
+        }
+        if block.rows != rows.len() || block.cols != cols.len() {
+            return Err(anyhow!(
+                "Cannot assign a {} by {} matrix to a {} by {} block.",
+                block.rows,
+                block.cols,
+                rows.len(),
+                cols.len()
+            ));
+        }
+        for (i, r) in rows.enumerate() {
+            self[r][cols.clone()].clone_from_slice(&block[i]);
+        }
+        Ok(())
+    }
+
+    /// `[self | b]`, the columns of `b` placed to the right.
+    pub fn hstack(&self, b: &Self) -> Result<Self, Error> {
+        if self.rows != b.rows {
+            return Err(anyhow!(
+                "Cannot place a {} by {} matrix beside a {} by {} matrix, the row counts differ.",
+                b.rows,
+                b.cols,
+                self.rows,
+                self.cols
+            ));
+        }
+        let mut stacked = Self::new(self.rows, self.cols + b.cols);
+        for r in 0..self.rows {
+            stacked[r][..self.cols].clone_from_slice(&self[r]);
+            stacked[r][self.cols..].clone_from_slice(&b[r]);
+        }
+        Ok(stacked)
+    }
+
+    /// The rows of `b` placed below.
+    pub fn vstack(&self, b: &Self) -> Result<Self, Error> {
+        if self.cols != b.cols {
+            return Err(anyhow!(
+                "Cannot place a {} by {} matrix below a {} by {} matrix, the column counts differ.",
+                b.rows,
+                b.cols,
+                self.rows,
+                self.cols
+            ));
+        }
+        let mut data = self.data.clone();
+        data.extend_from_slice(&b.data);
+        Ok(Self {
+            rows: self.rows + b.rows,
+            cols: self.cols,
+            data,
+        })
+    }
+
+    pub fn conjugate_transpose(&self) -> Self {
+        self.transpose().map(T::conj)
     }
 
     pub fn transpose(&self) -> Self {
         let mut t = Self::new(self.cols, self.rows);
         for i in 0..self.rows {
             for j in 0..self.cols {
-                t[j][i] = self[i][j];
+                t[j][i] = self[i][j].clone();
             }
         }
         t
     }
 
-    // #[allow(dead_code)]
-    // pub fn trace(&self) -> f64 {
-    //     if self.rows != self.cols {
-    //         panic!(
-    //             "Trace requires matrix to be square. Input matrix was {:?}.",
-    //             self
-    //         );
-    //     }
-    //     let mut t: f64 = 0.0;
-    //     for i in 0..self.rows {
-    //         t += self[i][i];
-    //     }
-    //     t
-    // }
+    pub fn trace(&self) -> Result<T, Error> {
+        if self.rows != self.cols {
+            return Err(anyhow!(
+                "Trace requires matrix to be square. Input matrix was {} by {}.",
+                self.rows,
+                self.cols
+            ));
+        }
+        let mut t = T::zero();
+        for i in 0..self.rows {
+            t = t + self[i][i].clone();
+        }
+        Ok(t)
+    }
 
     pub fn inverse(&self) -> Result<Self, Error> {
-        let d = self.det()?;
-        if d == 0.0 {
+        if self.rows != self.cols {
+            return Err(anyhow!("Inverse requires matrix to be a square"));
+        }
+        let factors = self.lu_factor();
+        if factors.is_singular() {
             return Err(anyhow!("Determinant is zero! No inverse."));
         }
 
-        let mut inv = Self::new(self.rows, self.cols);
+        let mut inv = factors.solve(&Self::identity(self.rows));
+        let tol = inv.tolerance();
+        inv.zero_negligible(tol);
+        Ok(inv)
+    }
 
-        for row in 0..self.rows {
-            for col in 0..self.cols {
-                inv[row][col] = self.cofactor(row, col)?;
-            }
+    pub fn identity(n: usize) -> Self {
+        let mut eye = Self::new(n, n);
+        for r in 0..n {
+            eye[r][r] = T::one();
         }
+        eye
+    }
 
-        inv.correct();
-        inv = inv.transpose();
-        inv.apply(|x| x / d);
-        Ok(inv)
+    pub fn filled(rows: usize, cols: usize, value: T) -> Self {
+        Self {
+            rows,
+            cols,
+            data: vec![value; rows * cols],
+        }
     }
 
-    // #[allow(dead_code)]
-    // pub fn identity(&mut self) {
-    //     if self.rows != self.cols {
-    //         panic!("Not a square matrix.");
-    //     }
-    //     for r in 0..self.rows {
-    //         self[r][r] = 1.0;
-    //     }
-    // }
+    /// A vector becomes the diagonal of a square matrix, any other matrix
+    /// has its diagonal pulled out as a column.
+    pub fn diag(&self) -> Self {
+        if self.rows == 1 || self.cols == 1 {
+            let n = self.data.len();
+            let mut d = Self::new(n, n);
+            for (i, x) in self.data.iter().enumerate() {
+                d[i][i] = x.clone();
+            }
+            d
+        } else {
+            let n = self.rows.min(self.cols);
+            let mut d = Self::new(n, 1);
+            for i in 0..n {
+                d[i][0] = self[i][i].clone();
+            }
+            d
+        }
+    }
 
-    pub fn apply(&mut self, f: impl Fn(f64) -> f64) {
-        self.data = self.data.iter().map(|elem| f(*elem)).collect()
+    /// The same matrix with every entry converted by `f`.
+    pub fn map<U>(&self, f: impl Fn(&T) -> U) -> Matrix<U> {
+        Matrix {
+            rows: self.rows,
+            cols: self.cols,
+            data: self.data.iter().map(f).collect(),
+        }
     }
 
-    pub fn combine(&self, b: Self, f: impl Fn(f64, f64) -> f64) -> Result<Self, Error> {
+    pub fn combine(&self, b: Self, f: impl Fn(T, T) -> T) -> Result<Self, Error> {
         if self.rows != b.rows || self.cols != b.cols {
             return Err(anyhow!("Matrices must be of the same size."));
         }
@@ -199,58 +495,1971 @@ impl Matrix {
             .data
             .iter()
             .zip(b.data.iter())
-            .map(|(a, b)| f(*a, *b))
+            .map(|(a, b)| f(a.clone(), b.clone()))
             .collect();
         Ok(new_matrix)
     }
 
-    fn swap_rows(&mut self, row: usize) {
-        let mut n_r = 0;
-        for r in 0..self.rows {
-            if self[r][0] > 0.0 {
-                n_r = r;
-                break;
-            }
+    fn swap_rows(&mut self, a: usize, b: usize) {
+        if a == b {
+            return;
         }
-        let temp: Vec<f64> = self[row].to_vec();
         for c in 0..self.cols {
-            self[row][c] = self[n_r][c];
-            self[n_r][c] = temp[n_r * self.cols + c];
+            self.data.swap(a * self.cols + c, b * self.cols + c);
+        }
+    }
+
+    /// Row at or below `from` with the largest entry in column `col`.
+    fn pivot(&self, from: usize, col: usize) -> usize {
+        (from..self.rows)
+            .max_by(|&a, &b| {
+                self[a][col]
+                    .magnitude()
+                    .total_cmp(&self[b][col].magnitude())
+            })
+            .unwrap_or(from)
+    }
+
+    /// Largest absolute column sum.
+    pub fn norm_1(&self) -> f64 {
+        (0..self.cols)
+            .map(|c| (0..self.rows).map(|r| self[r][c].magnitude()).sum::<f64>())
+            .fold(0.0, f64::max)
+    }
+
+    /// Largest absolute row sum.
+    pub fn norm_inf(&self) -> f64 {
+        (0..self.rows)
+            .map(|r| self[r].iter().map(|x| x.magnitude()).sum::<f64>())
+            .fold(0.0, f64::max)
+    }
+
+    pub fn norm_frobenius(&self) -> f64 {
+        self.data
+            .iter()
+            .map(|x| x.magnitude() * x.magnitude())
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    /// Sets the entries within `tol` of zero, rounding noise and -0 alike,
+    /// to exactly zero.
+    fn zero_negligible(&mut self, tol: f64) {
+        for elem in self.data.iter_mut() {
+            if elem.is_negligible(tol) {
+                *elem = T::zero();
+            }
+        }
+    }
+
+    /// Magnitude below which an entry is considered zero during elimination,
+    /// scaled to the size of the matrix and its largest entry.
+    fn tolerance(&self) -> f64 {
+        let largest = self
+            .data
+            .iter()
+            .fold(0.0, |acc: f64, x| acc.max(x.magnitude()));
+        f64::EPSILON * self.rows.max(self.cols) as f64 * largest
+    }
+}
+
+impl<T: FloatField> Matrix<T> {
+    /// Symmetric for real matrices.
+    fn is_hermitian(&self) -> bool {
+        let tol = self.tolerance();
+        self.rows == self.cols
+            && (0..self.rows)
+                .all(|i| (0..=i).all(|j| (self[i][j] - self[j][i].conj()).magnitude() <= tol))
+    }
+
+    /// Cholesky factorization of a symmetric (Hermitian) positive definite
+    /// matrix, returning the lower triangular L with `A = L L*`.
+    pub fn chol(&self) -> Result<Self, Error> {
+        if !self.is_hermitian() {
+            return Err(anyhow!("Cholesky requires a symmetric matrix"));
+        }
+        let n = self.rows;
+        let mut l = Self::new(n, n);
+        for j in 0..n {
+            let d = self[j][j].real()
+                - (0..j)
+                    .map(|k| l[j][k].magnitude() * l[j][k].magnitude())
+                    .sum::<f64>();
+            if d <= 0.0 {
+                return Err(anyhow!("Matrix is not positive definite"));
+            }
+            l[j][j] = T::from_real(d.sqrt());
+            for i in j + 1..n {
+                let s = (0..j).fold(T::zero(), |acc, k| acc + l[i][k] * l[j][k].conj());
+                l[i][j] = (self[i][j] - s) / l[j][j];
+            }
         }
+        Ok(l)
     }
 
-    fn correct(&mut self) {
-        for row in 0..self.rows {
-            for col in 0..self.cols {
-                let elem = self[row][col];
-                if elem == -0.0 {
-                    self[row][col] = 0.0;
+    /// Householder QR decomposition. Returns `(Q, R)` with `A = Q R`, Q an
+    /// m by m orthogonal (unitary) matrix and R m by n upper triangular with
+    /// a real, non-negative diagonal.
+    pub fn qr(&self) -> (Self, Self) {
+        let factors = self.householder(false);
+        let mut q = Self::identity(self.rows);
+        factors.apply_q(&mut q);
+        let mut r = factors.r;
+        for k in 0..self.rows.min(self.cols) {
+            // move the phase of the diagonal entry from R to Q, adding zero
+            // so that zeros stay +0
+            let d = r[k][k];
+            if d != T::from_real(d.magnitude()) {
+                let phase = d / T::from_real(d.magnitude());
+                r[k].iter_mut()
+                    .for_each(|x| *x = *x * phase.conj() + T::zero());
+                r[k][k] = T::from_real(d.magnitude());
+                for i in 0..self.rows {
+                    q[i][k] = q[i][k] * phase + T::zero();
                 }
-                let floored = elem.floor();
-                if elem - floored > 0.9999999 {
-                    self[row][col] = elem.round();
+            }
+        }
+        (q, r)
+    }
+
+    /// Householder QR, optionally moving the remaining column with the
+    /// largest norm into place at each step so that A P = Q R.
+    fn householder(&self, pivot: bool) -> Householder<T> {
+        let mut r = self.clone();
+        let mut perm: Vec<usize> = (0..self.cols).collect();
+        let mut reflectors = Vec::new();
+        for k in 0..self.rows.min(self.cols) {
+            let col_norm = |r: &Self, c: usize| {
+                (k..r.rows)
+                    .map(|i| r[i][c].magnitude() * r[i][c].magnitude())
+                    .sum::<f64>()
+            };
+            if pivot {
+                let best = (k..self.cols)
+                    .max_by(|&a, &b| col_norm(&r, a).total_cmp(&col_norm(&r, b)))
+                    .unwrap_or(k);
+                if best != k {
+                    for i in 0..self.rows {
+                        r.data.swap(i * self.cols + k, i * self.cols + best);
+                    }
+                    perm.swap(k, best);
+                }
+            }
+
+            // a column already zero below the diagonal needs no reflection,
+            // and skipping it keeps Q exact instead of off by an ulp
+            let Some((v, alpha)) = reflector(&r, k, k) else {
+                reflectors.push(vec![T::zero(); self.rows]);
+                continue;
+            };
+            reflect(&v, &mut r, k);
+            r[k][k] = alpha;
+            for i in k + 1..self.rows {
+                r[i][k] = T::zero();
+            }
+            reflectors.push(v);
+        }
+        Householder {
+            reflectors,
+            r,
+            perm,
+        }
+    }
+
+    /// Thin singular value decomposition by one-sided Jacobi rotations.
+    /// Returns `(U, S, V*)` with `A = U S V*`, where for k the smaller
+    /// dimension U is m by k, S is k by k diagonal with the singular values in
+    /// descending order, and V* is k by n. S is real for complex matrices too.
+    pub fn svd(&self) -> (Self, Matrix, Self) {
+        if self.rows < self.cols {
+            let (u, sigma, vh) = self.conjugate_transpose().svd();
+            return (vh.conjugate_transpose(), sigma, u.conjugate_transpose());
+        }
+        let (m, n) = (self.rows, self.cols);
+        let mut u = self.clone();
+        let mut v = Self::identity(n);
+        // the plane rotation [c, -s phase; s phase*, c] on columns p and q,
+        // where phase makes the inner product of the two columns real
+        let rotate = |w: &mut Self, p: usize, q: usize, c: T, s: T, phase: T| {
+            for i in 0..w.rows {
+                let (wp, wq) = (w[i][p], w[i][q]);
+                w[i][p] = c * wp - s * phase * wq;
+                w[i][q] = s * phase.conj() * wp + c * wq;
+            }
+        };
+        for _sweep in 0..100 {
+            let mut rotated = false;
+            for p in 0..n {
+                for q in p + 1..n {
+                    let (mut alpha, mut beta, mut gamma) = (0.0, 0.0, T::zero());
+                    for i in 0..m {
+                        alpha += u[i][p].magnitude() * u[i][p].magnitude();
+                        beta += u[i][q].magnitude() * u[i][q].magnitude();
+                        gamma = gamma + u[i][p].conj() * u[i][q];
+                    }
+                    let size = gamma.magnitude();
+                    if size <= f64::EPSILON * (alpha * beta).sqrt() {
+                        continue;
+                    }
+                    rotated = true;
+                    let phase = gamma.conj() / T::from_real(size);
+                    let zeta = (beta - alpha) / (2.0 * size);
+                    let t = zeta.signum() / (zeta.abs() + (1.0 + zeta * zeta).sqrt());
+                    let c = 1.0 / (1.0 + t * t).sqrt();
+                    let (c, s) = (T::from_real(c), T::from_real(c * t));
+                    rotate(&mut u, p, q, c, s, phase);
+                    rotate(&mut v, p, q, c, s, phase);
+                }
+            }
+            if !rotated {
+                break;
+            }
+        }
+
+        // the columns of u are now orthogonal, their norms are the singular
+        // values
+        let norms: Vec<f64> = (0..n)
+            .map(|j| {
+                (0..m)
+                    .map(|i| u[i][j].magnitude() * u[i][j].magnitude())
+                    .sum::<f64>()
+                    .sqrt()
+            })
+            .collect();
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&a, &b| norms[b].total_cmp(&norms[a]));
+
+        let mut left = Self::new(m, n);
+        let mut sigma = Matrix::new(n, n);
+        let mut vh = Self::new(n, n);
+        for (j, &k) in order.iter().enumerate() {
+            sigma[j][j] = norms[k];
+        }
+        // columns for singular values lost in rounding are noise, so they
+        // are left zero for complete_orthonormal to fill in
+        let tol = svd_tolerance(self, &sigma);
+        for (j, &k) in order.iter().enumerate() {
+            for i in 0..m {
+                left[i][j] = if norms[k] > tol {
+                    u[i][k] / T::from_real(norms[k])
+                } else {
+                    T::zero()
+                };
+            }
+            for i in 0..n {
+                vh[j][i] = v[i][k].conj();
+            }
+        }
+        left.complete_orthonormal();
+        (left, sigma, vh)
+    }
+
+    /// Replaces zero columns, left behind by zero singular values, with unit
+    /// vectors orthogonal to every other column.
+    fn complete_orthonormal(&mut self) {
+        for j in 0..self.cols {
+            if (0..self.rows).any(|i| !self[i][j].is_zero()) {
+                continue;
+            }
+            for e in 0..self.rows {
+                let mut w = vec![T::zero(); self.rows];
+                w[e] = T::one();
+                // orthogonalize twice, once is not enough in floating point
+                for _ in 0..2 {
+                    for k in (0..self.cols).filter(|&k| k != j) {
+                        let d = (0..self.rows)
+                            .map(|i| self[i][k].conj() * w[i])
+                            .fold(T::zero(), |acc, x| acc + x);
+                        for (i, wi) in w.iter_mut().enumerate() {
+                            *wi = *wi - d * self[i][k];
+                        }
+                    }
                 }
-                if elem > 0.0 && elem < 0.000001 {
-                    self[row][col] = 0.0;
+                let norm = w
+                    .iter()
+                    .map(|x| x.magnitude() * x.magnitude())
+                    .sum::<f64>()
+                    .sqrt();
+                if norm > 0.5 {
+                    for (i, wi) in w.into_iter().enumerate() {
+                        self[i][j] = wi / T::from_real(norm);
+                    }
+                    break;
                 }
-                if elem < 0.0 && elem > -0.00001 {
-                    self[row][col] = 0.0;
+            }
+        }
+    }
+
+    /// Moore-Penrose pseudoinverse V S+ U*, inverting only the singular
+    /// values above the rank tolerance.
+    pub fn pinv(&self) -> Self {
+        let (u, sigma, vh) = self.svd();
+        let tol = svd_tolerance(self, &sigma);
+        let mut pinv = Self::new(self.cols, self.rows);
+        for k in 0..sigma.rows {
+            if sigma[k][k] <= tol {
+                continue;
+            }
+            for i in 0..self.cols {
+                for j in 0..self.rows {
+                    pinv[i][j] =
+                        pinv[i][j] + vh[k][i].conj() * u[j][k].conj() / T::from_real(sigma[k][k]);
                 }
             }
         }
+        pinv
     }
-}
 
-impl Index<usize> for Matrix {
-    type Output = [f64];
+    /// Spectral norm, the largest singular value.
+    pub fn norm(&self) -> f64 {
+        let (_, sigma, _) = self.svd();
+        spectral_norm(&sigma)
+    }
 
-    fn index(&self, index: usize) -> &Self::Output {
-        &self.data[index * self.cols..(index + 1) * self.cols]
+    /// 2-norm condition number, the ratio of the largest to the smallest
+    /// singular value. Infinite when the matrix is rank deficient.
+    pub fn cond(&self) -> f64 {
+        let (_, sigma, _) = self.svd();
+        condition_number(self, &sigma)
     }
 }
 
-impl IndexMut<usize> for Matrix {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        &mut self.data[index * self.cols..(index + 1) * self.cols]
+impl Matrix {
+    /// Numerical rank: the number of singular values above the tolerance.
+    fn svd_rank(&self) -> usize {
+        let (_, sigma, _) = self.svd();
+        let tol = svd_tolerance(self, &sigma);
+        (0..sigma.rows).filter(|&i| sigma[i][i] > tol).count()
+    }
+
+    /// Triangular A is solved by substitution and symmetric positive
+    /// definite A through its Cholesky factor, so `chol` output can be fed
+    /// straight back in. Everything else is left to elimination.
+    fn solve_float(&self, b: &Self) -> Result<Self, Error> {
+        let nonsingular = (0..self.rows.min(self.cols)).all(|i| self[i][i] != 0.0);
+        if self.rows == self.cols && nonsingular {
+            if self.is_lower_triangular() {
+                return Ok(self.forward_substitute(b));
+            }
+            if self.transpose().is_lower_triangular() {
+                return Ok(self.back_substitute(b));
+            }
+            if let Ok(l) = self.chol() {
+                return Ok(l.transpose().back_substitute(&l.forward_substitute(b)));
+            }
+        }
+        self.solve_by_elimination(b)
+    }
+
+    /// LDL' factorization of a symmetric matrix with Bunch-Kaufman pivoting.
+    /// Returns `(L, D, P)` with `P A P' = L D L'`, where L is unit lower
+    /// triangular and D block diagonal with 1 by 1 and symmetric 2 by 2
+    /// blocks. Unlike `chol` the matrix may be indefinite or singular.
+    pub fn ldl(&self) -> Result<(Self, Self, Self), Error> {
+        if !self.is_hermitian() {
+            return Err(anyhow!("LDL' requires a symmetric matrix"));
+        }
+        // bounds the growth of the multipliers, from Bunch and Kaufman
+        let alpha = (1.0 + 17f64.sqrt()) / 8.0;
+        let n = self.rows;
+        let mut a = self.clone();
+        let mut l = Self::identity(n);
+        let mut d = Self::new(n, n);
+        let mut perm: Vec<usize> = (0..n).collect();
+        let mut k = 0;
+        while k < n {
+            let diagonal = a[k][k].abs();
+            let (imax, colmax) = (k + 1..n)
+                .map(|i| (i, a[i][k].abs()))
+                .fold((k, 0.0), |best, x| if x.1 > best.1 { x } else { best });
+            let (pivot, width) = if diagonal.max(colmax) == 0.0 || diagonal >= alpha * colmax {
+                (k, 1)
+            } else {
+                let rowmax = (k..n)
+                    .filter(|&j| j != imax)
+                    .map(|j| a[imax][j].abs())
+                    .fold(0.0, f64::max);
+                if diagonal * rowmax >= alpha * colmax * colmax {
+                    (k, 1)
+                } else if a[imax][imax].abs() >= alpha * rowmax {
+                    (imax, 1)
+                } else {
+                    (imax, 2)
+                }
+            };
+
+            // move the pivot to the last row and column of this block
+            let last = k + width - 1;
+            if pivot != last {
+                for i in 0..n {
+                    a.data.swap(i * n + last, i * n + pivot);
+                }
+                for j in 0..n {
+                    a.data.swap(last * n + j, pivot * n + j);
+                }
+                for j in 0..k {
+                    l.data.swap(last * n + j, pivot * n + j);
+                }
+                perm.swap(last, pivot);
+            }
+
+            if width == 1 {
+                d[k][k] = a[k][k];
+                if d[k][k] != 0.0 {
+                    for i in k + 1..n {
+                        l[i][k] = a[i][k] / d[k][k];
+                    }
+                }
+            } else {
+                let (e11, e21, e22) = (a[k][k], a[k + 1][k], a[k + 1][k + 1]);
+                d[k][k] = e11;
+                d[k][k + 1] = e21;
+                d[k + 1][k] = e21;
+                d[k + 1][k + 1] = e22;
+                let det = e11 * e22 - e21 * e21;
+                for i in k + 2..n {
+                    let (x, y) = (a[i][k], a[i][k + 1]);
+                    l[i][k] = (x * e22 - y * e21) / det;
+                    l[i][k + 1] = (y * e11 - x * e21) / det;
+                }
+            }
+            for i in k + width..n {
+                for j in k + width..n {
+                    a[i][j] -= (k..k + width).map(|c| l[i][c] * a[j][c]).sum::<f64>();
+                }
+            }
+            k += width;
+        }
+
+        let mut p = Self::new(n, n);
+        for (i, &j) in perm.iter().enumerate() {
+            p[i][j] = 1.0;
+        }
+        Ok((l, d, p))
+    }
+
+    fn is_lower_triangular(&self) -> bool {
+        (0..self.rows).all(|i| self[i].iter().skip(i + 1).all(|&x| x == 0.0))
+    }
+
+    /// Solves L X = B for square lower triangular L with a non-zero diagonal.
+    fn forward_substitute(&self, b: &Self) -> Self {
+        let mut x = b.clone();
+        for col in 0..b.cols {
+            for i in 0..self.rows {
+                let s: f64 = (0..i).map(|j| self[i][j] * x[j][col]).sum();
+                x[i][col] = (x[i][col] - s) / self[i][i];
+            }
+        }
+        x
+    }
+
+    /// Solves U X = B for square upper triangular U with a non-zero diagonal.
+    fn back_substitute(&self, b: &Self) -> Self {
+        let mut x = b.clone();
+        for col in 0..b.cols {
+            for i in (0..self.rows).rev() {
+                let s: f64 = (i + 1..self.rows).map(|j| self[i][j] * x[j][col]).sum();
+                x[i][col] = (x[i][col] - s) / self[i][i];
+            }
+        }
+        x
+    }
+
+    /// Minimum-norm least-squares solution of A X = B. Returns X together
+    /// with the residual B - A X. Uses a column-pivoted Householder QR, and
+    /// when A is rank deficient a second QR of the leading rows of R (a
+    /// complete orthogonal decomposition) to pick the shortest solution.
+    pub fn lstsq(&self, b: &Self) -> Result<(Self, Self), Error> {
+        if self.rows != b.rows {
+            return Err(anyhow!(
+                "Dimensions not matched. A has {} rows, B has {} rows.",
+                self.rows,
+                b.rows
+            ));
+        }
+        let factors = self.householder(true);
+        let r = &factors.r;
+        let tol = f64::EPSILON
+            * self.rows.max(self.cols) as f64
+            * r.data.first().map_or(0.0, |x| x.abs());
+        let rank = (0..self.rows.min(self.cols))
+            .take_while(|&k| r[k][k].abs() > tol)
+            .count();
+
+        let mut qtb = b.clone();
+        factors.apply_qt(&mut qtb);
+
+        // the leading rank rows of R, [R11 R12], are full row rank; factor
+        // their transpose as Z [T; 0] so that [R11 R12] = [T' 0] Z'
+        let mut leading = Self::new(self.cols, rank);
+        for i in 0..rank {
+            for j in i..self.cols {
+                leading[j][i] = r[i][j];
+            }
+        }
+        let z = leading.householder(false);
+
+        let mut y = Self::new(self.cols, b.cols);
+        for col in 0..b.cols {
+            // T' is lower triangular, forward substitute for the first rank
+            // entries and leave the rest zero for the minimum norm
+            for i in 0..rank {
+                let mut sum = qtb[i][col];
+                for j in 0..i {
+                    sum -= z.r[j][i] * y[j][col];
+                }
+                y[i][col] = sum / z.r[i][i];
+            }
+        }
+        z.apply_q(&mut y);
+
+        let mut x = Self::new(self.cols, b.cols);
+        for (j, &p) in factors.perm.iter().enumerate() {
+            x[p].copy_from_slice(&y[j]);
+        }
+        let residual = b.combine(self.dot(x.clone())?, |a, b| a - b)?;
+        Ok((x, residual))
+    }
+
+    /// Eigenvalues and eigenvectors of a square matrix, with one unit
+    /// eigenvector per column.
+    ///
+    /// Symmetric matrices use Jacobi rotations and give real eigenvalues in
+    /// ascending order. Other matrices are reduced to Hessenberg form and
+    /// then to real Schur form by shifted QR. If any eigenvalue is complex
+    /// the whole result is, with conjugate pairs `a + bi`, `a - bi` next to
+    /// each other.
+    pub fn eig(&self) -> Result<Eigen, Error> {
+        if self.rows != self.cols {
+            return Err(anyhow!("Eigenvalues require matrix to be a square"));
+        }
+        let (re, im, mut vectors) = if self.is_hermitian() {
+            self.jacobi_eig()
+        } else {
+            self.schur_eig()
+        };
+
+        let n = self.rows;
+        let mut j = 0;
+        while j < n {
+            let width = if im[j] == 0.0 { 1 } else { 2 };
+            let norm = (0..n)
+                .flat_map(|i| vectors[i][j..j + width].to_vec())
+                .map(|x| x * x)
+                .sum::<f64>()
+                .sqrt();
+            if norm > 0.0 {
+                for i in 0..n {
+                    vectors[i][j..j + width].iter_mut().for_each(|x| *x /= norm);
+                }
+            }
+            j += width;
+        }
+
+        if im.iter().all(|&x| x == 0.0) {
+            let values = Self {
+                rows: n,
+                cols: 1,
+                data: re,
+            };
+            return Ok(Eigen::Real(values, vectors));
+        }
+        let (values, vectors) = complex_eigenpairs(&re, &im, &vectors);
+        Ok(Eigen::Complex(values, vectors))
+    }
+
+    /// Cyclic Jacobi eigenvalue algorithm for symmetric matrices.
+    fn jacobi_eig(&self) -> (Vec<f64>, Vec<f64>, Self) {
+        let n = self.rows;
+        let mut a = self.clone();
+        let mut v = Self::identity(n);
+        let tiny = f64::EPSILON * self.tolerance().max(f64::MIN_POSITIVE);
+        for _sweep in 0..100 {
+            let off: f64 = (0..n)
+                .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+                .map(|(i, j)| a[i][j] * a[i][j])
+                .sum();
+            if off.sqrt() <= tiny {
+                break;
+            }
+            for p in 0..n {
+                for q in p + 1..n {
+                    if a[p][q] == 0.0 {
+                        continue;
+                    }
+                    let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                    let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                    let c = 1.0 / (t * t + 1.0).sqrt();
+                    let s = t * c;
+                    for k in 0..n {
+                        let (kp, kq) = (a[k][p], a[k][q]);
+                        a[k][p] = c * kp - s * kq;
+                        a[k][q] = s * kp + c * kq;
+                    }
+                    for k in 0..n {
+                        let (pk, qk) = (a[p][k], a[q][k]);
+                        a[p][k] = c * pk - s * qk;
+                        a[q][k] = s * pk + c * qk;
+                    }
+                    for k in 0..n {
+                        let (kp, kq) = (v[k][p], v[k][q]);
+                        v[k][p] = c * kp - s * kq;
+                        v[k][q] = s * kp + c * kq;
+                    }
+                }
+            }
+        }
+
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&x, &y| a[x][x].total_cmp(&a[y][y]));
+        let mut vectors = Self::new(n, n);
+        for (j, &k) in order.iter().enumerate() {
+            for i in 0..n {
+                vectors[i][j] = v[i][k];
+            }
+        }
+        (
+            order.iter().map(|&k| a[k][k]).collect(),
+            vec![0.0; n],
+            vectors,
+        )
+    }
+
+    /// Eigen-decomposition of a general real matrix: Householder reduction
+    /// to upper Hessenberg form, then the Francis double shift QR iteration
+    /// to real Schur form, then back substitution for the eigenvectors.
+    /// Follows `orthes` and `hqr2` from EISPACK.
+    fn schur_eig(&self) -> (Vec<f64>, Vec<f64>, Self) {
+        let nn = self.rows;
+        let mut h = self.clone();
+        let mut v = Self::new(nn, nn);
+        let mut ort = vec![0.0; nn];
+
+        // reduce to Hessenberg form
+        let high = nn - 1;
+        for m in 1..high {
+            let scale: f64 = (m..=high).map(|i| h[i][m - 1].abs()).sum();
+            if scale == 0.0 {
+                continue;
+            }
+            let mut hh = 0.0;
+            for i in (m..=high).rev() {
+                ort[i] = h[i][m - 1] / scale;
+                hh += ort[i] * ort[i];
+            }
+            let mut g = hh.sqrt();
+            if ort[m] > 0.0 {
+                g = -g;
+            }
+            hh -= ort[m] * g;
+            ort[m] -= g;
+            for j in m..nn {
+                let f = (m..=high).rev().map(|i| ort[i] * h[i][j]).sum::<f64>() / hh;
+                for i in m..=high {
+                    h[i][j] -= f * ort[i];
+                }
+            }
+            for i in 0..=high {
+                let f = (m..=high).rev().map(|j| ort[j] * h[i][j]).sum::<f64>() / hh;
+                for j in m..=high {
+                    h[i][j] -= f * ort[j];
+                }
+            }
+            ort[m] *= scale;
+            h[m][m - 1] = scale * g;
+        }
+        for i in 0..nn {
+            v[i][i] = 1.0;
+        }
+        for m in (1..high).rev() {
+            if h[m][m - 1] == 0.0 {
+                continue;
+            }
+            for i in m + 1..=high {
+                ort[i] = h[i][m - 1];
+            }
+            for j in m..=high {
+                let g: f64 = (m..=high).map(|i| ort[i] * v[i][j]).sum();
+                // double division avoids possible underflow
+                let g = (g / ort[m]) / h[m][m - 1];
+                for i in m..=high {
+                    v[i][j] += g * ort[i];
+                }
+            }
+        }
+
+        // reduce Hessenberg to real Schur form
+        let mut d = vec![0.0; nn];
+        let mut e = vec![0.0; nn];
+        let eps = f64::EPSILON;
+        let mut exshift = 0.0;
+        let (mut r, mut s, mut z) = (0.0, 0.0, 0.0);
+        let (mut p, mut q, mut w, mut x, mut y): (f64, f64, f64, f64, f64);
+        let mut norm = 0.0;
+        for i in 0..nn {
+            for j in i.saturating_sub(1)..nn {
+                norm += h[i][j].abs();
+            }
+        }
+
+        let mut n = nn as isize - 1;
+        let mut iter = 0;
+        while n >= 0 {
+            let nu = n as usize;
+            // look for a single small sub-diagonal element
+            let mut l = nu;
+            while l > 0 {
+                s = h[l - 1][l - 1].abs() + h[l][l].abs();
+                if s == 0.0 {
+                    s = norm;
+                }
+                if h[l][l - 1].abs() < eps * s {
+                    break;
+                }
+                l -= 1;
+            }
+
+            if l == nu {
+                // one root found
+                h[nu][nu] += exshift;
+                d[nu] = h[nu][nu];
+                e[nu] = 0.0;
+                n -= 1;
+                iter = 0;
+            } else if l == nu - 1 {
+                // two roots found
+                w = h[nu][nu - 1] * h[nu - 1][nu];
+                p = (h[nu - 1][nu - 1] - h[nu][nu]) / 2.0;
+                q = p * p + w;
+                z = q.abs().sqrt();
+                h[nu][nu] += exshift;
+                h[nu - 1][nu - 1] += exshift;
+                x = h[nu][nu];
+
+                if q >= 0.0 {
+                    // real pair
+                    z = if p >= 0.0 { p + z } else { p - z };
+                    d[nu - 1] = x + z;
+                    d[nu] = d[nu - 1];
+                    if z != 0.0 {
+                        d[nu] = x - w / z;
+                    }
+                    e[nu - 1] = 0.0;
+                    e[nu] = 0.0;
+                    x = h[nu][nu - 1];
+                    s = x.abs() + z.abs();
+                    p = x / s;
+                    q = z / s;
+                    r = (p * p + q * q).sqrt();
+                    p /= r;
+                    q /= r;
+                    for j in nu - 1..nn {
+                        z = h[nu - 1][j];
+                        h[nu - 1][j] = q * z + p * h[nu][j];
+                        h[nu][j] = q * h[nu][j] - p * z;
+                    }
+                    for i in 0..=nu {
+                        z = h[i][nu - 1];
+                        h[i][nu - 1] = q * z + p * h[i][nu];
+                        h[i][nu] = q * h[i][nu] - p * z;
+                    }
+                    for i in 0..=high {
+                        z = v[i][nu - 1];
+                        v[i][nu - 1] = q * z + p * v[i][nu];
+                        v[i][nu] = q * v[i][nu] - p * z;
+                    }
+                } else {
+                    // complex pair
+                    d[nu - 1] = x + p;
+                    d[nu] = x + p;
+                    e[nu - 1] = z;
+                    e[nu] = -z;
+                }
+                n -= 2;
+                iter = 0;
+            } else {
+                // no convergence yet, form the shift
+                x = h[nu][nu];
+                y = 0.0;
+                w = 0.0;
+                if l < nu {
+                    y = h[nu - 1][nu - 1];
+                    w = h[nu][nu - 1] * h[nu - 1][nu];
+                }
+
+                // Wilkinson's original ad hoc shift
+                if iter == 10 {
+                    exshift += x;
+                    for i in 0..=nu {
+                        h[i][i] -= x;
+                    }
+                    s = h[nu][nu - 1].abs() + h[nu - 1][nu - 2].abs();
+                    x = 0.75 * s;
+                    y = x;
+                    w = -0.4375 * s * s;
+                }
+
+                // MATLAB's ad hoc shift
+                if iter == 30 {
+                    s = (y - x) / 2.0;
+                    s = s * s + w;
+                    if s > 0.0 {
+                        s = s.sqrt();
+                        if y < x {
+                            s = -s;
+                        }
+                        s = x - w / ((y - x) / 2.0 + s);
+                        for i in 0..=nu {
+                            h[i][i] -= s;
+                        }
+                        exshift += s;
+                        x = 0.964;
+                        y = x;
+                        w = x;
+                    }
+                }
+
+                iter += 1;
+
+                // look for two consecutive small sub-diagonal elements
+                let mut m = nu - 2;
+                loop {
+                    z = h[m][m];
+                    r = x - z;
+                    s = y - z;
+                    p = (r * s - w) / h[m + 1][m] + h[m][m + 1];
+                    q = h[m + 1][m + 1] - z - r - s;
+                    r = h[m + 2][m + 1];
+                    s = p.abs() + q.abs() + r.abs();
+                    p /= s;
+                    q /= s;
+                    r /= s;
+                    if m == l {
+                        break;
+                    }
+                    if h[m][m - 1].abs() * (q.abs() + r.abs())
+                        < eps
+                            * (p.abs() * (h[m - 1][m - 1].abs() + z.abs() + h[m + 1][m + 1].abs()))
+                    {
+                        break;
+                    }
+                    m -= 1;
+                }
+
+                for i in m + 2..=nu {
+                    h[i][i - 2] = 0.0;
+                    if i > m + 2 {
+                        h[i][i - 3] = 0.0;
+                    }
+                }
+
+                // double QR step involving rows l..=n and columns m..=n
+                for k in m..nu {
+                    let notlast = k != nu - 1;
+                    if k != m {
+                        p = h[k][k - 1];
+                        q = h[k + 1][k - 1];
+                        r = if notlast { h[k + 2][k - 1] } else { 0.0 };
+                        x = p.abs() + q.abs() + r.abs();
+                        if x == 0.0 {
+                            continue;
+                        }
+                        p /= x;
+                        q /= x;
+                        r /= x;
+                    }
+
+                    s = (p * p + q * q + r * r).sqrt();
+                    if p < 0.0 {
+                        s = -s;
+                    }
+                    if s == 0.0 {
+                        continue;
+                    }
+                    if k != m {
+                        h[k][k - 1] = -s * x;
+                    } else if l != m {
+                        h[k][k - 1] = -h[k][k - 1];
+                    }
+                    p += s;
+                    x = p / s;
+                    y = q / s;
+                    z = r / s;
+                    q /= p;
+                    r /= p;
+
+                    for j in k..nn {
+                        p = h[k][j] + q * h[k + 1][j];
+                        if notlast {
+                            p += r * h[k + 2][j];
+                            h[k + 2][j] -= p * z;
+                        }
+                        h[k][j] -= p * x;
+                        h[k + 1][j] -= p * y;
+                    }
+                    for i in 0..=nu.min(k + 3) {
+                        p = x * h[i][k] + y * h[i][k + 1];
+                        if notlast {
+                            p += z * h[i][k + 2];
+                            h[i][k + 2] -= p * r;
+                        }
+                        h[i][k] -= p;
+                        h[i][k + 1] -= p * q;
+                    }
+                    for i in 0..=high {
+                        p = x * v[i][k] + y * v[i][k + 1];
+                        if notlast {
+                            p += z * v[i][k + 2];
+                            v[i][k + 2] -= p * r;
+                        }
+                        v[i][k] -= p;
+                        v[i][k + 1] -= p * q;
+                    }
+                }
+            }
+        }
+
+        if norm == 0.0 {
+            return (d, e, v);
+        }
+
+        // back substitute to find the vectors of the upper triangular form
+        for n in (0..nn).rev() {
+            p = d[n];
+            q = e[n];
+            if q == 0.0 {
+                // real vector
+                let mut l = n;
+                h[n][n] = 1.0;
+                for i in (0..n).rev() {
+                    w = h[i][i] - p;
+                    r = (l..=n).map(|j| h[i][j] * h[j][n]).sum();
+                    if e[i] < 0.0 {
+                        z = w;
+                        s = r;
+                        continue;
+                    }
+                    l = i;
+                    if e[i] == 0.0 {
+                        h[i][n] = if w != 0.0 { -r / w } else { -r / (eps * norm) };
+                    } else {
+                        // solve real equations
+                        x = h[i][i + 1];
+                        y = h[i + 1][i];
+                        q = (d[i] - p) * (d[i] - p) + e[i] * e[i];
+                        let t = (x * s - z * r) / q;
+                        h[i][n] = t;
+                        h[i + 1][n] = if x.abs() > z.abs() {
+                            (-r - w * t) / x
+                        } else {
+                            (-s - y * t) / z
+                        };
+                    }
+                    // overflow control
+                    let t = h[i][n].abs();
+                    if (eps * t) * t > 1.0 {
+                        for j in i..=n {
+                            h[j][n] /= t;
+                        }
+                    }
+                }
+            } else if q < 0.0 {
+                // complex vector, the last component is imaginary so the
+                // matrix is triangular
+                let mut l = n - 1;
+                if h[n][n - 1].abs() > h[n - 1][n].abs() {
+                    h[n - 1][n - 1] = q / h[n][n - 1];
+                    h[n - 1][n] = -(h[n][n] - p) / h[n][n - 1];
+                } else {
+                    let (cr, ci) = cdiv(0.0, -h[n - 1][n], h[n - 1][n - 1] - p, q);
+                    h[n - 1][n - 1] = cr;
+                    h[n - 1][n] = ci;
+                }
+                h[n][n - 1] = 0.0;
+                h[n][n] = 1.0;
+                for i in (0..n - 1).rev() {
+                    let ra: f64 = (l..=n).map(|j| h[i][j] * h[j][n - 1]).sum();
+                    let sa: f64 = (l..=n).map(|j| h[i][j] * h[j][n]).sum();
+                    w = h[i][i] - p;
+                    if e[i] < 0.0 {
+                        z = w;
+                        r = ra;
+                        s = sa;
+                        continue;
+                    }
+                    l = i;
+                    if e[i] == 0.0 {
+                        let (cr, ci) = cdiv(-ra, -sa, w, q);
+                        h[i][n - 1] = cr;
+                        h[i][n] = ci;
+                    } else {
+                        // solve complex equations
+                        x = h[i][i + 1];
+                        y = h[i + 1][i];
+                        let mut vr = (d[i] - p) * (d[i] - p) + e[i] * e[i] - q * q;
+                        let vi = (d[i] - p) * 2.0 * q;
+                        if vr == 0.0 && vi == 0.0 {
+                            vr = eps * norm * (w.abs() + q.abs() + x.abs() + y.abs() + z.abs());
+                        }
+                        let (cr, ci) =
+                            cdiv(x * r - z * ra + q * sa, x * s - z * sa - q * ra, vr, vi);
+                        h[i][n - 1] = cr;
+                        h[i][n] = ci;
+                        if x.abs() > z.abs() + q.abs() {
+                            h[i + 1][n - 1] = (-ra - w * h[i][n - 1] + q * h[i][n]) / x;
+                            h[i + 1][n] = (-sa - w * h[i][n] - q * h[i][n - 1]) / x;
+                        } else {
+                            let (cr, ci) = cdiv(-r - y * h[i][n - 1], -s - y * h[i][n], z, q);
+                            h[i + 1][n - 1] = cr;
+                            h[i + 1][n] = ci;
+                        }
+                    }
+                    // overflow control
+                    let t = h[i][n - 1].abs().max(h[i][n].abs());
+                    if (eps * t) * t > 1.0 {
+                        for j in i..=n {
+                            h[j][n - 1] /= t;
+                            h[j][n] /= t;
+                        }
+                    }
+                }
+            }
+        }
+
+        // back transform to get the eigenvectors of the original matrix
+        for j in (0..nn).rev() {
+            for i in 0..=high {
+                z = (0..=j.min(high)).map(|k| v[i][k] * h[k][j]).sum();
+                v[i][j] = z;
+            }
+        }
+        (d, e, v)
+    }
+
+    /// Entries drawn uniformly from [0, 1).
+    pub fn random(rows: usize, cols: usize, rng: &mut impl Rng) -> Self {
+        Self {
+            rows,
+            cols,
+            data: (0..rows * cols).map(|_| rng.gen::<f64>()).collect(),
+        }
+    }
+}
+
+impl Matrix<Complex64> {
+    /// Eigenvalues and unit eigenvectors, one per column, from the complex
+    /// Schur form: Householder reduction to Hessenberg form, shifted QR down
+    /// to a triangular T, and back substitution for the eigenvectors of T.
+    pub fn eig(&self) -> Result<(Self, Self), Error> {
+        if self.rows != self.cols {
+            return Err(anyhow!("Eigenvalues require matrix to be a square"));
+        }
+        let n = self.rows;
+        let mut h = self.clone();
+        let mut q = Self::identity(n);
+        for k in 0..n.saturating_sub(2) {
+            if let Some((v, _)) = reflector(&h, k + 1, k) {
+                reflect(&v, &mut h, k + 1);
+                reflect_columns(&v, &mut h);
+                reflect_columns(&v, &mut q);
+                for i in k + 2..n {
+                    h[i][k] = Complex64::new(0.0, 0.0);
+                }
+            }
+        }
+
+        let scale = self.norm_frobenius().max(f64::MIN_POSITIVE);
+        let mut hi = n.saturating_sub(1);
+        let mut iterations = 0;
+        let mut since_deflation = 0;
+        while hi > 0 {
+            let mut lo = hi;
+            while lo > 0 {
+                let near = h[lo - 1][lo - 1].norm() + h[lo][lo].norm();
+                let near = if near == 0.0 { scale } else { near };
+                if h[lo][lo - 1].norm() <= f64::EPSILON * near {
+                    h[lo][lo - 1] = Complex64::new(0.0, 0.0);
+                    break;
+                }
+                lo -= 1;
+            }
+            if lo == hi {
+                hi -= 1;
+                since_deflation = 0;
+                continue;
+            }
+            iterations += 1;
+            since_deflation += 1;
+            if iterations > 100 * n {
+                return Err(anyhow!("Eigenvalues did not converge"));
+            }
+
+            // Wilkinson shift, the eigenvalue of the trailing 2 by 2 block
+            // closer to its last entry, with an occasional kick off it
+            let (a, b, c, d) = (h[hi - 1][hi - 1], h[hi - 1][hi], h[hi][hi - 1], h[hi][hi]);
+            let mean = (a + d) / 2.0;
+            let root = ((a - d) * (a - d) / 4.0 + b * c).sqrt();
+            let mut shift = if (mean + root - d).norm() <= (mean - root - d).norm() {
+                mean + root
+            } else {
+                mean - root
+            };
+            if since_deflation % 10 == 0 {
+                shift += c.norm();
+            }
+
+            for i in lo..=hi {
+                h[i][i] -= shift;
+            }
+            let mut rotations = Vec::new();
+            for k in lo..hi {
+                let (x, y) = (h[k][k], h[k + 1][k]);
+                let r = (x.norm_sqr() + y.norm_sqr()).sqrt();
+                let (c, s) = if r == 0.0 {
+                    (Complex64::new(1.0, 0.0), Complex64::new(0.0, 0.0))
+                } else {
+                    (x / r, y / r)
+                };
+                for j in k..n {
+                    let (p, r) = (h[k][j], h[k + 1][j]);
+                    h[k][j] = c.conj() * p + s.conj() * r;
+                    h[k + 1][j] = c * r - s * p;
+                }
+                rotations.push((k, c, s));
+            }
+            for (k, c, s) in rotations {
+                let rotate = |m: &mut Self, rows: usize| {
+                    for i in 0..rows {
+                        let (p, r) = (m[i][k], m[i][k + 1]);
+                        m[i][k] = p * c + r * s;
+                        m[i][k + 1] = r * c.conj() - p * s.conj();
+                    }
+                };
+                rotate(&mut h, (k + 2).min(hi) + 1);
+                rotate(&mut q, n);
+            }
+            for i in lo..=hi {
+                h[i][i] += shift;
+            }
+        }
+
+        let mut values = Self::new(n, 1);
+        let mut vectors = Self::new(n, n);
+        let tiny = f64::EPSILON * scale;
+        for k in 0..n {
+            let lambda = h[k][k];
+            values[k][0] = lambda;
+            let mut y = vec![Complex64::new(0.0, 0.0); n];
+            y[k] = Complex64::new(1.0, 0.0);
+            for i in (0..k).rev() {
+                let s: Complex64 = (i + 1..=k).map(|j| h[i][j] * y[j]).sum();
+                let mut pivot = h[i][i] - lambda;
+                if pivot.norm() < tiny {
+                    pivot = Complex64::new(tiny, 0.0);
+                }
+                y[i] = -s / pivot;
+            }
+            let mut v: Vec<Complex64> = (0..n)
+                .map(|i| (0..=k).map(|j| q[i][j] * y[j]).sum())
+                .collect();
+            let norm = v.iter().map(|x| x.norm_sqr()).sum::<f64>().sqrt();
+            v.iter_mut().for_each(|x| *x /= norm);
+            for i in 0..n {
+                vectors[i][k] = v[i];
+            }
+        }
+        Ok((values, vectors))
+    }
+}
+
+/// Singular values at or below this are treated as zero.
+fn svd_tolerance<T>(m: &Matrix<T>, sigma: &Matrix) -> f64 {
+    let largest = if sigma.rows > 0 { sigma[0][0] } else { 0.0 };
+    f64::EPSILON * m.rows.max(m.cols) as f64 * largest
+}
+
+/// Largest of the singular values of m, sorted in descending order.
+fn spectral_norm(sigma: &Matrix) -> f64 {
+    if sigma.rows > 0 {
+        sigma[0][0]
+    } else {
+        0.0
+    }
+}
+
+/// Ratio of the largest to the smallest singular value of m, infinite when
+/// the smallest is within the rank tolerance.
+fn condition_number<T>(m: &Matrix<T>, sigma: &Matrix) -> f64 {
+    if sigma.rows == 0 {
+        return 0.0;
+    }
+    let smallest = sigma[sigma.rows - 1][sigma.rows - 1];
+    if smallest <= svd_tolerance(m, sigma) {
+        return f64::INFINITY;
+    }
+    sigma[0][0] / smallest
+}
+
+/// Complex division (xr + xi i) / (yr + yi i), scaled to avoid overflow.
+fn cdiv(xr: f64, xi: f64, yr: f64, yi: f64) -> (f64, f64) {
+    if yr.abs() > yi.abs() {
+        let r = yi / yr;
+        let d = yr + r * yi;
+        ((xr + r * xi) / d, (xi - r * xr) / d)
+    } else {
+        let r = yr / yi;
+        let d = yi + r * yr;
+        ((r * xr + xi) / d, (r * xi - xr) / d)
+    }
+}
+
+/// Householder vector v, zero above row `from`, whose reflection takes
+/// column `col` of m onto row `from`, together with the entry it leaves
+/// there. `None` when the column is already zero below `from`.
+fn reflector<T: FloatField>(m: &Matrix<T>, from: usize, col: usize) -> Option<(Vec<T>, T)> {
+    if (from + 1..m.rows).all(|i| m[i][col].is_zero()) {
+        return None;
+    }
+    let norm = (from..m.rows)
+        .map(|i| m[i][col].magnitude() * m[i][col].magnitude())
+        .sum::<f64>()
+        .sqrt();
+    let x = m[from][col];
+    let phase = if x.is_zero() {
+        T::one()
+    } else {
+        x / T::from_real(x.magnitude())
+    };
+    let alpha = -(phase * T::from_real(norm));
+    let mut v = vec![T::zero(); m.rows];
+    for i in from..m.rows {
+        v[i] = m[i][col];
+    }
+    // alpha has the opposite phase to x, so this adds rather than cancels
+    v[from] = v[from] - alpha;
+    Some((v, alpha))
+}
+
+/// m = m (I - 2 v v* / v* v)
+fn reflect_columns<T: FloatField>(v: &[T], m: &mut Matrix<T>) {
+    let vv: f64 = v.iter().map(|x| x.magnitude() * x.magnitude()).sum();
+    for r in 0..m.rows {
+        let s = (0..m.cols).fold(T::zero(), |acc, j| acc + m[r][j] * v[j]);
+        let s = s * T::from_real(2.0) / T::from_real(vv);
+        for j in 0..m.cols {
+            m[r][j] = m[r][j] - s * v[j].conj();
+        }
+    }
+}
+
+/// Result of `Matrix::householder`: R, the column permutation, and the
+/// reflectors whose product is Q. A zero reflector stands for the identity.
+struct Householder<T> {
+    reflectors: Vec<Vec<T>>,
+    r: Matrix<T>,
+    perm: Vec<usize>,
+}
+
+impl<T: FloatField> Householder<T> {
+    /// Overwrites `b` with Q* b.
+    fn apply_qt(&self, b: &mut Matrix<T>) {
+        for (k, v) in self.reflectors.iter().enumerate() {
+            reflect(v, b, k);
+        }
+    }
+
+    /// Overwrites `b` with Q b.
+    fn apply_q(&self, b: &mut Matrix<T>) {
+        for (k, v) in self.reflectors.iter().enumerate().rev() {
+            reflect(v, b, k);
+        }
+    }
+}
+
+/// Applies the reflection I - 2 v v* / v* v to every column of `m`, where
+/// `v` is zero above row `from`.
+fn reflect<T: FloatField>(v: &[T], m: &mut Matrix<T>, from: usize) {
+    let vv: f64 = v[from..]
+        .iter()
+        .map(|x| x.magnitude() * x.magnitude())
+        .sum();
+    if vv == 0.0 {
+        return;
+    }
+    for c in 0..m.cols {
+        let s = (from..m.rows).fold(T::zero(), |acc, i| acc + v[i].conj() * m[i][c]);
+        let f = s * T::from_real(2.0) / T::from_real(vv);
+        for i in from..m.rows {
+            m[i][c] = m[i][c] - f * v[i];
+        }
+    }
+}
+
+/// An elementary row operation performed by `rref`, rows counted from 0.
+#[derive(Debug, PartialEq, Clone)]
+pub enum RowOp<T> {
+    Swap(usize, usize),
+    /// The row divided by `divisor`, its pivot.
+    Scale {
+        row: usize,
+        divisor: T,
+    },
+    /// `factor` times row `source` taken away from row `target`.
+    Subtract {
+        target: usize,
+        source: usize,
+        factor: T,
+    },
+}
+
+impl<T> RowOp<T> {
+    /// The same operation with its scalar converted by `f`.
+    pub fn map<U>(self, f: impl Fn(T) -> U) -> RowOp<U> {
+        match self {
+            RowOp::Swap(a, b) => RowOp::Swap(a, b),
+            RowOp::Scale { row, divisor } => RowOp::Scale {
+                row,
+                divisor: f(divisor),
+            },
+            RowOp::Subtract {
+                target,
+                source,
+                factor,
+            } => RowOp::Subtract {
+                target,
+                source,
+                factor: f(factor),
+            },
+        }
+    }
+}
+
+/// Packed result of `Matrix::lu_factor`: the strict lower triangle of `lu`
+/// holds L (whose diagonal is all ones) and the rest holds U. Row i of P A is
+/// row `perm[i]` of A, and `sign` is the determinant of P.
+struct LuFactors<T> {
+    lu: Matrix<T>,
+    perm: Vec<usize>,
+    sign: T,
+    tol: f64,
+}
+
+impl<T: Field> LuFactors<T> {
+    fn is_singular(&self) -> bool {
+        (0..self.lu.rows.min(self.lu.cols)).any(|i| self.lu[i][i].is_negligible(self.tol))
+    }
+
+    /// Solves A X = B for a square, non-singular A by forward substitution
+    /// through L and back substitution through U, one column of B at a time.
+    fn solve(&self, b: &Matrix<T>) -> Matrix<T> {
+        let n = self.lu.rows;
+        let mut x = Matrix::new(n, b.cols);
+        for col in 0..b.cols {
+            let mut y: Vec<T> = self.perm.iter().map(|&p| b[p][col].clone()).collect();
+            for i in 0..n {
+                for j in 0..i {
+                    y[i] = y[i].clone() - self.lu[i][j].clone() * y[j].clone();
+                }
+            }
+            for i in (0..n).rev() {
+                for j in i + 1..n {
+                    y[i] = y[i].clone() - self.lu[i][j].clone() * y[j].clone();
+                }
+                y[i] = y[i].clone() / self.lu[i][i].clone();
+            }
+            for (i, value) in y.into_iter().enumerate() {
+                x[i][col] = value;
+            }
+        }
+        x
+    }
+}
+
+impl<T> Index<usize> for Matrix<T> {
+    type Output = [T];
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.data[index * self.cols..(index + 1) * self.cols]
+    }
+}
+
+impl<T> IndexMut<usize> for Matrix<T> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.data[index * self.cols..(index + 1) * self.cols]
+    }
+}
+
+impl Field for f64 {
+    fn zero() -> Self {
+        0.0
+    }
+
+    fn one() -> Self {
+        1.0
+    }
+
+    fn magnitude(&self) -> f64 {
+        self.abs()
+    }
+
+    fn power(&self, exponent: &Self) -> Option<Self> {
+        Some(self.powf(*exponent))
+    }
+
+    fn from_integer(i: i32) -> Self {
+        i as f64
+    }
+
+    fn from_double(d: f64) -> Option<Self> {
+        Some(d)
+    }
+
+    fn from_rational(r: &BigRational) -> Option<Self> {
+        r.to_f64()
+    }
+
+    fn rank(m: &Matrix<Self>) -> usize {
+        m.svd_rank()
+    }
+
+    fn solve(a: &Matrix<Self>, b: &Matrix<Self>) -> Result<Matrix<Self>, Error> {
+        a.solve_float(b)
+    }
+
+    fn tidy_det(det: Self, n: usize) -> Self {
+        // elimination leaves a few ulps of error, which would otherwise turn
+        // integer determinants into 42.99999999999999 or 6e-16
+        let slack = 64.0 * n as f64 * f64::EPSILON * det.abs().max(1.0);
+        if (det - det.round()).abs() <= slack {
+            det.round() + 0.0
+        } else {
+            det
+        }
+    }
+}
+
+impl FloatField for f64 {
+    fn from_real(x: f64) -> Self {
+        x
+    }
+
+    fn real(&self) -> f64 {
+        *self
+    }
+}
+
+impl Field for Complex64 {
+    fn zero() -> Self {
+        Complex64::new(0.0, 0.0)
+    }
+
+    fn one() -> Self {
+        Complex64::new(1.0, 0.0)
+    }
+
+    fn magnitude(&self) -> f64 {
+        self.norm()
+    }
+
+    fn power(&self, exponent: &Self) -> Option<Self> {
+        // repeated multiplication keeps (1+2i)^2 at exactly -3+4i
+        if exponent.im == 0.0 && exponent.re.fract() == 0.0 && exponent.re.abs() <= i32::MAX as f64
+        {
+            return Some(self.powi(exponent.re as i32));
+        }
+        Some(self.powc(*exponent))
+    }
+
+    fn from_integer(i: i32) -> Self {
+        Complex64::new(i as f64, 0.0)
+    }
+
+    fn from_double(d: f64) -> Option<Self> {
+        Some(Complex64::new(d, 0.0))
+    }
+
+    fn from_rational(r: &BigRational) -> Option<Self> {
+        Some(Complex64::new(r.to_f64()?, 0.0))
+    }
+
+    fn from_complex(z: &Complex64) -> Option<Self> {
+        Some(*z)
+    }
+
+    fn conj(&self) -> Self {
+        Complex64::conj(self)
+    }
+
+    fn tidy_det(det: Self, n: usize) -> Self {
+        Complex64::new(f64::tidy_det(det.re, n), f64::tidy_det(det.im, n))
+    }
+}
+
+impl FloatField for Complex64 {
+    fn from_real(x: f64) -> Self {
+        Complex64::new(x, 0.0)
+    }
+
+    fn real(&self) -> f64 {
+        self.re
+    }
+}
+
+/// Eigenvalues and eigenvectors from `Matrix::eig`.
+pub enum Eigen {
+    Real(Matrix, Matrix),
+    Complex(Matrix<Complex64>, Matrix<Complex64>),
+}
+
+/// Turns the real Schur form of the eigenvectors, where a conjugate pair at
+/// j, j + 1 shares the vector with real part in column j and imaginary part
+/// in column j + 1, into one complex eigenvector per column.
+fn complex_eigenpairs(
+    values_re: &[f64],
+    values_im: &[f64],
+    vectors: &Matrix,
+) -> (Matrix<Complex64>, Matrix<Complex64>) {
+    let n = values_re.len();
+    let mut complex_values = Matrix::new(n, 1);
+    let mut complex_vectors = Matrix::new(n, n);
+    let mut j = 0;
+    while j < n {
+        let (re, im) = (values_re[j], values_im[j]);
+        if im == 0.0 {
+            complex_values[j][0] = Complex64::new(re, 0.0);
+            for i in 0..n {
+                complex_vectors[i][j] = Complex64::new(vectors[i][j], 0.0);
+            }
+            j += 1;
+            continue;
+        }
+        complex_values[j][0] = Complex64::new(re, im);
+        complex_values[j + 1][0] = Complex64::new(re, -im);
+        for i in 0..n {
+            let v = Complex64::new(vectors[i][j], vectors[i][j + 1]);
+            complex_vectors[i][j] = v;
+            complex_vectors[i][j + 1] = v.conj();
+        }
+        j += 2;
+    }
+    (complex_values, complex_vectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::Modular;
+
+    fn matrix(rows: usize, cols: usize, data: &[f64]) -> Matrix {
+        Matrix {
+            rows,
+            cols,
+            data: data.to_vec(),
+        }
+    }
+
+    /// a = b up to rounding relative to the size of b.
+    fn assert_close<T: Field>(a: &Matrix<T>, b: &Matrix<T>) {
+        let error = a.clone().combine(b.clone(), |x, y| x - y).unwrap();
+        assert!(
+            error.norm_frobenius() <= 1e-12 * b.norm_frobenius(),
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn solve_paths_agree() {
+        let b = matrix(3, 2, &[1.0, 0.0, 2.0, -1.0, 3.0, 4.0]);
+        let lower = matrix(3, 3, &[2.0, 0.0, 0.0, 1.0, 3.0, 0.0, -1.0, 2.0, 4.0]);
+        let spd = matrix(3, 3, &[4.0, 2.0, -2.0, 2.0, 5.0, 1.0, -2.0, 1.0, 6.0]);
+        let general = matrix(3, 3, &[0.0, 2.0, 1.0, 1.0, 1.0, 1.0, 3.0, -1.0, 2.0]);
+        // forward substitution, back substitution, Cholesky and elimination
+        for a in [&lower, &lower.transpose(), &spd, &general] {
+            let x = a.solve(&b).unwrap();
+            assert_close(&x, &a.solve_by_elimination(&b).unwrap());
+            assert_close(&a.dot(x).unwrap(), &b);
+        }
+    }
+
+    #[test]
+    fn solve_errors() {
+        let singular = matrix(2, 2, &[1.0, 2.0, 2.0, 4.0]);
+        let error = singular.solve(&matrix(2, 1, &[1.0, 3.0])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "System is inconsistent, it has no solution."
+        );
+        let error = singular.solve(&matrix(2, 1, &[1.0, 2.0])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Matrix is singular, the system has infinitely many solutions."
+        );
+        assert!(singular.solve(&matrix(3, 1, &[1.0, 2.0, 3.0])).is_err());
+
+        // overdetermined but consistent has a unique solution
+        let tall = matrix(3, 2, &[1.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+        let x = tall.solve(&matrix(3, 1, &[1.0, 2.0, 3.0])).unwrap();
+        assert_close(&x, &matrix(2, 1, &[1.0, 2.0]));
+    }
+
+    #[test]
+    fn lstsq_minimum_norm() {
+        let line = matrix(3, 2, &[1.0, 1.0, 1.0, 2.0, 1.0, 3.0]);
+        let (x, residual) = line.lstsq(&matrix(3, 1, &[1.0, 2.0, 2.0])).unwrap();
+        assert_close(&x, &matrix(2, 1, &[2.0 / 3.0, 0.5]));
+        assert_close(
+            &residual,
+            &matrix(3, 1, &[-1.0 / 6.0, 1.0 / 3.0, -1.0 / 6.0]),
+        );
+
+        // rank deficient: every x with x1 + x2 = 2 fits, [1; 1] is shortest
+        let ones = Matrix::filled(3, 2, 1.0);
+        let (x, residual) = ones.lstsq(&matrix(3, 1, &[1.0, 2.0, 3.0])).unwrap();
+        assert_close(&x, &matrix(2, 1, &[1.0, 1.0]));
+        assert_close(&residual, &matrix(3, 1, &[-1.0, 0.0, 1.0]));
+
+        let scaled = matrix(2, 2, &[1e7, 0.0, 0.0, 2e7]);
+        let (x, _) = scaled.lstsq(&matrix(2, 1, &[1.0, 1.0])).unwrap();
+        assert_close(&x, &matrix(2, 1, &[1e-7, 5e-8]));
+    }
+
+    #[test]
+    fn qr_real() {
+        for a in [
+            matrix(3, 3, &[4.0, 3.0, 1.0, 3.0, 2.0, 5.0, 6.0, 7.0, 2.0]),
+            matrix(3, 2, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+            matrix(2, 3, &[-1.0, 2.0, 0.0, 0.0, 3.0, 1.0]),
+            matrix(2, 2, &[1e-7, 0.0, 0.0, 2e-7]),
+        ] {
+            let (q, r) = a.qr();
+            assert_close(
+                &q.transpose().dot(q.clone()).unwrap(),
+                &Matrix::identity(a.rows),
+            );
+            assert_close(&q.dot(r.clone()).unwrap(), &a);
+            for i in 0..r.rows {
+                assert!((0..i.min(r.cols)).all(|j| r[i][j] == 0.0));
+            }
+            assert!((0..a.rows.min(a.cols)).all(|k| r[k][k] >= 0.0));
+        }
+    }
+
+    #[test]
+    fn svd_pinv_and_rank() {
+        for (a, rank) in [
+            (matrix(2, 3, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]), 2),
+            (matrix(3, 2, &[2.0, -1.0, 0.0, 1.0, 1.0, 3.0]), 2),
+            (
+                matrix(3, 3, &[1.0, 2.0, 3.0, 2.0, 4.0, 6.0, 1.0, 1.0, 1.0]),
+                2,
+            ),
+        ] {
+            let (u, sigma, vt) = a.svd();
+            assert_close(&u.dot(sigma.clone()).unwrap().dot(vt).unwrap(), &a);
+            assert!((1..sigma.rows).all(|k| sigma[k][k] <= sigma[k - 1][k - 1]));
+            assert!((0..sigma.rows).all(|k| sigma[k][k] >= 0.0));
+
+            let p = a.pinv();
+            assert_close(&a.dot(p.clone()).unwrap().dot(a.clone()).unwrap(), &a);
+            assert_close(&p.dot(a.clone()).unwrap().dot(p.clone()).unwrap(), &p);
+            assert_eq!(a.rank(), rank);
+        }
+
+        // the tolerance is relative to the largest singular value
+        let small = matrix(2, 2, &[1e-7, 0.0, 0.0, 2e-7]);
+        assert_eq!(small.rank(), 2);
+        assert!((small.cond() - 2.0).abs() <= 1e-12);
+        let lost = matrix(2, 2, &[1.0, 0.0, 0.0, 1e-17]);
+        assert_eq!(lost.rank(), 1);
+        assert_eq!(lost.cond(), f64::INFINITY);
+        assert_eq!(lost.pinv().data, vec![1.0, 0.0, 0.0, 0.0]);
+    }
+
+    fn rational_matrix(rows: usize, cols: usize, data: &[(i64, i64)]) -> Matrix<BigRational> {
+        Matrix {
+            rows,
+            cols,
+            data: data
+                .iter()
+                .map(|&(n, d)| BigRational::new(n.into(), d.into()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn rational_elimination_is_exact() {
+        let a = rational_matrix(2, 2, &[(1, 2), (1, 3), (1, 4), (1, 5)]);
+        assert_eq!(a.det().unwrap(), BigRational::new(1.into(), 60.into()));
+        let inv = a.inverse().unwrap();
+        assert_eq!(
+            inv,
+            rational_matrix(2, 2, &[(12, 1), (-20, 1), (-15, 1), (30, 1)])
+        );
+        assert_eq!(a.dot(inv).unwrap(), Matrix::identity(2));
+
+        let singular = rational_matrix(2, 3, &[(1, 3), (2, 3), (1, 1), (1, 6), (1, 3), (1, 2)]);
+        assert_eq!(
+            singular.rref(),
+            rational_matrix(2, 3, &[(1, 1), (2, 1), (3, 1), (0, 1), (0, 1), (0, 1)])
+        );
+        assert_eq!(singular.rank(), 1);
+        assert!(rational_matrix(2, 2, &[(1, 3), (2, 3), (1, 6), (1, 3)])
+            .inverse()
+            .is_err());
+    }
+
+    #[test]
+    fn inverse_badly_scaled() {
+        let diagonal = matrix(2, 2, &[2e6, 0.0, 0.0, 4.0]);
+        assert_eq!(diagonal.inverse().unwrap().data, vec![5e-7, 0.0, 0.0, 0.25]);
+        for a in [
+            diagonal,
+            matrix(2, 2, &[1e6, 1.0, 1.0, 1e-3]),
+            matrix(3, 3, &[1e-8, 2e-8, 0.0, 3.0, 1.0, 5.0, 0.0, 1e4, 2e4]),
+        ] {
+            assert_close(
+                &a.dot(a.inverse().unwrap()).unwrap(),
+                &Matrix::identity(a.rows),
+            );
+        }
+    }
+
+    #[test]
+    fn rref_wide_singular_and_scaled() {
+        let wide = matrix(2, 3, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert_close(
+            &wide.rref(),
+            &matrix(2, 3, &[1.0, 0.0, -1.0, 0.0, 1.0, 2.0]),
+        );
+
+        let singular = matrix(3, 3, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+        let reduced = singular.rref();
+        assert_close(
+            &reduced,
+            &matrix(3, 3, &[1.0, 0.0, -1.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0]),
+        );
+        assert_eq!(reduced[2], [0.0, 0.0, 0.0]);
+        assert_eq!(singular.elimination_rank(), 2);
+
+        // small entries are kept, only rounding noise relative to the
+        // matrix is dropped
+        let scaled = matrix(2, 2, &[1.0, 1e-7, 2.0, 2e-7]);
+        assert_close(&scaled.rref(), &matrix(2, 2, &[1.0, 1e-7, 0.0, 0.0]));
+        let tiny = matrix(2, 2, &[1e-10, 2e-10, 3e-10, 4e-10]);
+        assert_close(&tiny.rref(), &Matrix::identity(2));
+    }
+
+    #[test]
+    fn null_space_is_annihilated() {
+        let singular = matrix(3, 3, &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+        let wide = matrix(2, 4, &[1.0, 2.0, 0.0, 3.0, 2.0, 4.0, 1.0, 0.0]);
+        for a in [&singular, &wide] {
+            let null = a.null_space();
+            assert_eq!(null.cols, a.cols - a.elimination_rank());
+            let product = a.dot(null).unwrap();
+            assert!(product.norm_frobenius() <= 1e-12 * a.norm_frobenius());
+        }
+        assert_eq!(Matrix::<f64>::identity(3).null_space().cols, 0);
+
+        let scaled = matrix(2, 2, &[1.0, 1e-7, 2.0, 2e-7]);
+        assert_eq!(scaled.null_space(), matrix(2, 1, &[-1e-7, 1.0]));
+        // a pivot row with a zero in the free column gives +0, not -0
+        let null = matrix(2, 3, &[1.0, 2.0, 0.0, 2.0, 4.0, 0.0]).null_space();
+        assert!(null.data.iter().all(|x| x.is_sign_positive() || *x != 0.0));
+
+        let rational = rational_matrix(2, 3, &[(1, 2), (1, 3), (1, 1), (1, 4), (1, 6), (1, 2)]);
+        let null = rational.null_space();
+        assert_eq!(null.cols, 2);
+        assert!(rational.dot(null).unwrap().data.iter().all(|x| x.is_zero()));
+
+        // independent over the rationals, but not mod 5
+        let modular = Matrix {
+            rows: 2,
+            cols: 3,
+            data: [1, 2, 3, 3, 1, 4].map(|x| Modular::new(x, 5)).to_vec(),
+        };
+        let null = modular.null_space();
+        assert_eq!(null.cols, 2);
+        assert!(modular.dot(null).unwrap().data.iter().all(|x| x.is_zero()));
+    }
+
+    /// Checks A V = V Λ up to rounding relative to the size of A, and that
+    /// every eigenvector has unit length.
+    fn assert_eigenpairs(a: &Matrix) -> Eigen {
+        let eigen = a.eig().unwrap();
+        let (values, vectors) = match &eigen {
+            Eigen::Real(values, vectors) => (values.map(|&x| x.into()), vectors.map(|&x| x.into())),
+            Eigen::Complex(values, vectors) => (values.clone(), vectors.clone()),
+        };
+        let n = a.rows;
+        let av = a.map(|&x| Complex64::from(x)).dot(vectors.clone()).unwrap();
+        let mut residual = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                residual += (av[i][j] - vectors[i][j] * values[j][0]).norm_sqr();
+            }
+        }
+        let scale = a.norm_frobenius().max(f64::MIN_POSITIVE);
+        assert!(
+            residual.sqrt() <= 1e-12 * scale,
+            "residual {} for {a:?}",
+            residual.sqrt()
+        );
+        for j in 0..n {
+            let length: f64 = (0..n).map(|i| vectors[i][j].norm_sqr()).sum();
+            assert!((length.sqrt() - 1.0).abs() <= 1e-12);
+        }
+        eigen
+    }
+
+    #[test]
+    fn chol_positive_definite_only() {
+        let spd = matrix(3, 3, &[4.0, 2.0, -2.0, 2.0, 5.0, 1.0, -2.0, 1.0, 6.0]);
+        let l = spd.chol().unwrap();
+        assert!(l.is_lower_triangular());
+        assert_close(&l.dot(l.transpose()).unwrap(), &spd);
+
+        let indefinite = matrix(2, 2, &[1.0, 2.0, 2.0, 1.0]);
+        let error = indefinite.chol().unwrap_err();
+        assert_eq!(error.to_string(), "Matrix is not positive definite");
+        assert!(matrix(2, 2, &[1.0, 2.0, 3.0, 4.0]).chol().is_err());
+    }
+
+    #[test]
+    fn ldl_reconstructs() {
+        for a in [
+            matrix(2, 2, &[0.0, 1.0, 1.0, 0.0]),
+            matrix(3, 3, &[0.0, 1.0, 2.0, 1.0, 0.0, 3.0, 2.0, 3.0, 0.0]),
+            matrix(3, 3, &[4.0, 2.0, -2.0, 2.0, -3.0, 1.0, -2.0, 1.0, 0.0]),
+        ] {
+            let (l, d, p) = a.ldl().unwrap();
+            let pap = p.dot(a.clone()).unwrap().dot(p.transpose()).unwrap();
+            let ldl = l.dot(d).unwrap().dot(l.transpose()).unwrap();
+            let error = pap.combine(ldl, |x, y| x - y).unwrap().norm_frobenius();
+            assert!(error <= 1e-12 * a.norm_frobenius(), "{a:?}");
+        }
+    }
+
+    #[test]
+    fn eig_symmetric() {
+        assert_eigenpairs(&matrix(2, 2, &[2.0, 1.0, 1.0, 2.0]));
+        assert_eigenpairs(&matrix(
+            3,
+            3,
+            &[4.0, 1.0, -2.0, 1.0, 3.0, 0.5, -2.0, 0.5, 1.0],
+        ));
+        match assert_eigenpairs(&matrix(2, 2, &[1e-7, 0.0, 0.0, 2e-7])) {
+            Eigen::Real(values, _) => assert_eq!(values.data, vec![1e-7, 2e-7]),
+            Eigen::Complex(..) => panic!("diagonal matrix has real eigenvalues"),
+        }
+    }
+
+    #[test]
+    fn eig_nonsymmetric() {
+        assert!(matches!(
+            assert_eigenpairs(&matrix(2, 2, &[1.0, 2.0, 3.0, 4.0])),
+            Eigen::Real(..)
+        ));
+        assert_eigenpairs(&matrix(
+            3,
+            3,
+            &[2.0, 0.0, 1.0, 1.0, 3.0, -1.0, 0.0, 4.0, 5.0],
+        ));
+    }
+
+    #[test]
+    fn eig_complex_pair() {
+        assert_eigenpairs(&matrix(2, 2, &[0.0, -1.0, 1.0, 0.0]));
+        assert_eigenpairs(&matrix(
+            3,
+            3,
+            &[1.0, -2.0, 0.0, 2.0, 1.0, 0.0, 0.0, 0.0, 3.0],
+        ));
+        match assert_eigenpairs(&matrix(2, 2, &[1.0, -1e-7, 1e-7, 1.0])) {
+            Eigen::Complex(values, _) => {
+                assert!((values[0][0] - Complex64::new(1.0, 1e-7)).norm() <= 1e-15);
+                assert_eq!(values[1][0], values[0][0].conj());
+            }
+            Eigen::Real(..) => panic!("rotation has complex eigenvalues"),
+        }
+    }
+
+    fn complex_matrix(rows: usize, cols: usize, data: &[(f64, f64)]) -> Matrix<Complex64> {
+        Matrix {
+            rows,
+            cols,
+            data: data
+                .iter()
+                .map(|&(re, im)| Complex64::new(re, im))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn complex_decompositions() {
+        let a = complex_matrix(2, 2, &[(1.0, 2.0), (3.0, 0.0), (4.0, 0.0), (5.0, -1.0)]);
+        let wide = complex_matrix(
+            2,
+            3,
+            &[
+                (1.0, 1.0),
+                (0.0, 2.0),
+                (3.0, 0.0),
+                (0.0, -1.0),
+                (2.0, 0.0),
+                (1.0, 1.0),
+            ],
+        );
+        for m in [&a, &wide, &wide.conjugate_transpose()] {
+            let (q, r) = m.qr();
+            assert_close(&q.dot(r.clone()).unwrap(), m);
+            assert!((0..r.rows.min(r.cols)).all(|k| r[k][k].im == 0.0 && r[k][k].re >= 0.0));
+
+            let (u, sigma, vh) = m.svd();
+            let s = sigma.map(|&x| Complex64::from(x));
+            assert_close(&u.dot(s).unwrap().dot(vh).unwrap(), m);
+            assert_close(&m.dot(m.pinv()).unwrap().dot(m.clone()).unwrap(), m);
+        }
+
+        let b = complex_matrix(
+            4,
+            4,
+            &[
+                (1.0, 0.0),
+                (2.0, -1.0),
+                (0.0, 3.0),
+                (1.0, 1.0),
+                (0.5, 0.0),
+                (-1.0, 2.0),
+                (4.0, 0.0),
+                (0.0, 0.0),
+                (0.0, 1.0),
+                (1.0, 0.0),
+                (2.0, 2.0),
+                (-3.0, 0.0),
+                (2.0, 0.0),
+                (0.0, -1.0),
+                (1.0, 0.0),
+                (0.0, 0.5),
+            ],
+        );
+        for m in [&a, &b, &Matrix::identity(3)] {
+            let (values, vectors) = m.eig().unwrap();
+            let mut lambda = Matrix::new(m.rows, m.rows);
+            for k in 0..m.rows {
+                lambda[k][k] = values[k][0];
+            }
+            assert_close(
+                &m.dot(vectors.clone()).unwrap(),
+                &vectors.dot(lambda).unwrap(),
+            );
+        }
+
+        let h = complex_matrix(2, 2, &[(2.0, 0.0), (1.0, 1.0), (1.0, -1.0), (3.0, 0.0)]);
+        let l = h.chol().unwrap();
+        assert_close(&l.dot(l.conjugate_transpose()).unwrap(), &h);
+        assert!(a.chol().is_err());
+        assert!((h.cond() - 4.0).abs() <= 1e-12);
     }
 }
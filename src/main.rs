@@ -2,6 +2,7 @@ use std::path::Path;
 
 use anyhow::anyhow;
 
+mod field;
 mod interp;
 mod linalg;
 mod parser;
@@ -12,6 +13,11 @@ gflags::define! {
     -c, --compile: &Path
 }
 
+gflags::define! {
+    /// plain, latex, markdown or full
+    -f, --format: &str
+}
+
 fn get_extension(path: &str) -> Result<&str, anyhow::Error> {
     let ext = Path::new(path)
         .extension()
@@ -26,6 +32,15 @@ fn get_extension(path: &str) -> Result<&str, anyhow::Error> {
 
 fn main() -> Result<(), anyhow::Error> {
     let _patterns = gflags::parse();
+    if FORMAT.is_present() {
+        match types::Format::from_name(FORMAT.flag) {
+            Some(format) => types::FORMAT.with(|current| current.set(format)),
+            None => {
+                eprintln!("Error: unknown format {}", FORMAT.flag);
+                return Ok(());
+            }
+        }
+    }
     if COMPILE.is_present() {
         let path = COMPILE.flag.to_str().unwrap();
         match get_extension(path) {
@@ -38,12 +53,13 @@ fn main() -> Result<(), anyhow::Error> {
 
         let raw_file = std::fs::read_to_string(path)?;
 
-        let ast_root = parser::parse(&raw_file)?;
-        let res = interp::interp(&ast_root, None, false)?;
+        let session = interp::Session::default();
+        session.enter(COMPILE.flag)?;
+        let ast_root = session.parse(&raw_file)?;
+        let res = interp::interp(ast_root, None, false, &session)?;
         println!("{}", res);
         Ok(())
     } else {
-        // repl::repl()
-        Ok(())
+        repl::repl()
     }
 }
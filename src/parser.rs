@@ -1,5 +1,9 @@
 use self::AstNode::*;
 use anyhow::anyhow;
+use num_bigint::BigInt;
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::Zero;
 use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use pest_derive::Parser;
@@ -8,13 +12,67 @@ use pest_derive::Parser;
 #[grammar = "lala.pest"]
 pub struct LalaParser;
 
+/// The words `lala.pest` reserves in its `keyword` rule: `let`, `fun` and
+/// the word verbs. The REPL completes them; a test checks that the
+/// grammar still lists the same ones.
+pub const KEYWORDS: &[&str] = &[
+    "let",
+    "fun",
+    "rref",
+    "rref_steps",
+    "det",
+    "lu",
+    "qr",
+    "eig",
+    "svd",
+    "pinv",
+    "chol",
+    "ldl",
+    "lstsq",
+    "eye",
+    "diag",
+    "zeros",
+    "ones",
+    "rand",
+    "mod",
+    "tr",
+    "norm",
+    "norm1",
+    "normi",
+    "normf",
+    "cond",
+    "null",
+    "col",
+    "row",
+];
+
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum MonadicVerb {
     Rank,
     Inverse,
-    RREF,
+    Rref,
+    RrefSteps,
     Transpose,
+    ConjugateTranspose,
     Determinant,
+    Lu,
+    Qr,
+    Eigen,
+    Svd,
+    PseudoInverse,
+    Cholesky,
+    Ldl,
+    Trace,
+    Norm,
+    Norm1,
+    NormInf,
+    NormFrobenius,
+    Cond,
+    Eye,
+    Diag,
+    NullSpace,
+    ColumnSpace,
+    RowSpace,
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -22,12 +80,36 @@ pub enum DyadicVerb {
     Dot,
     Plus,
     Times,
+    Scale,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Solve,
+    LeastSquares,
+    Zeros,
+    Ones,
+    Random,
+    Modulo,
+    HorizontalConcat,
+    VerticalConcat,
+}
+
+/// One axis of an index expression: a single position or a half-open range
+/// whose missing ends default to the start and end of the axis.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Slice<'a> {
+    At(Box<AstNode<'a>>),
+    Range(Option<Box<AstNode<'a>>>, Option<Box<AstNode<'a>>>),
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum AstNode<'a> {
     Integer(i32),
     DoublePrecisionFloat(f64),
+    Rational(BigRational),
+    Complex(Complex64),
     MonadicOp {
         verb: MonadicVerb,
         expr: Box<AstNode<'a>>,
@@ -42,6 +124,22 @@ pub enum AstNode<'a> {
         ident: String,
         expr: Box<AstNode<'a>>,
     },
+    Destructure {
+        idents: Vec<String>,
+        expr: Box<AstNode<'a>>,
+    },
+    IndexAssignment {
+        ident: String,
+        rows: Slice<'a>,
+        cols: Slice<'a>,
+        expr: Box<AstNode<'a>>,
+    },
+    Tuple(Vec<AstNode<'a>>),
+    Index {
+        ident: String,
+        rows: Slice<'a>,
+        cols: Slice<'a>,
+    },
     Ident(String),
     Matrix(Vec<Vec<AstNode<'a>>>),
     Command((&'a str, Vec<&'a str>)),
@@ -51,20 +149,12 @@ pub enum AstNode<'a> {
 
 fn build_ast_from_term(pair: Pair<Rule>) -> Option<AstNode> {
     match pair.as_rule() {
-        Rule::integer => {
-            let istr = pair.as_str();
-            let (sign, istr) = match &istr[..1] {
-                "-" => (-1, &istr[1..]),
-                _ => (1, &istr[..]),
-            };
-            let int: i32 = istr.parse().unwrap();
-            Some(AstNode::Integer(sign * int))
-        }
+        Rule::integer => Some(AstNode::Integer(integer(pair.as_str()).ok()?)),
         Rule::decimal => {
             let dstr = pair.as_str();
             let (sign, dstr) = match &dstr[..1] {
                 "-" => (-1.0, &dstr[1..]),
-                _ => (1.0, &dstr[..]),
+                _ => (1.0, dstr),
             };
             let mut float: f64 = dstr.parse().unwrap();
             if float != 0.0 {
@@ -72,7 +162,30 @@ fn build_ast_from_term(pair: Pair<Rule>) -> Option<AstNode> {
             }
             Some(AstNode::DoublePrecisionFloat(float))
         }
-        Rule::expr => build_ast_from_expr(pair),
+        Rule::complex => {
+            let z = pair.as_str().trim_end_matches('i');
+            // the imaginary part starts at the last sign that isn't leading
+            let (re, im) = match z.rfind(['+', '-']).filter(|&k| k > 0) {
+                Some(k) => (z[..k].parse().ok()?, z[k..].parse().ok()?),
+                None => (0.0, z.parse().ok()?),
+            };
+            Some(AstNode::Complex(Complex64::new(re, im)))
+        }
+        Rule::rational => {
+            let (numer, denom) = pair.as_str().split_once('/')?;
+            let numer: BigInt = numer.parse().ok()?;
+            let denom: BigInt = denom.parse().ok()?;
+            if denom.is_zero() {
+                // left for the division verb to report
+                return Some(AstNode::DyadicOp {
+                    verb: DyadicVerb::Divide,
+                    lhs: Box::new(AstNode::Rational(BigRational::from_integer(numer))),
+                    rhs: Box::new(AstNode::Integer(0)),
+                });
+            }
+            Some(AstNode::Rational(BigRational::new(numer, denom)))
+        }
+        Rule::expr | Rule::ident => build_ast_from_expr(pair),
         _bad_term => None,
     }
 }
@@ -81,9 +194,29 @@ fn parse_monadic_verb<'a>(pair: Pair<Rule>, expr: AstNode<'a>) -> Option<AstNode
     let verb = match pair.as_str() {
         "#" => MonadicVerb::Rank,
         "?" => MonadicVerb::Inverse,
-        "rref" => MonadicVerb::RREF,
+        "rref" => MonadicVerb::Rref,
+        "rref_steps" => MonadicVerb::RrefSteps,
         "%" => MonadicVerb::Transpose,
+        "%*" => MonadicVerb::ConjugateTranspose,
         "det" => MonadicVerb::Determinant,
+        "lu" => MonadicVerb::Lu,
+        "qr" => MonadicVerb::Qr,
+        "eig" => MonadicVerb::Eigen,
+        "svd" => MonadicVerb::Svd,
+        "pinv" => MonadicVerb::PseudoInverse,
+        "chol" => MonadicVerb::Cholesky,
+        "ldl" => MonadicVerb::Ldl,
+        "tr" => MonadicVerb::Trace,
+        "norm" => MonadicVerb::Norm,
+        "norm1" => MonadicVerb::Norm1,
+        "normi" => MonadicVerb::NormInf,
+        "normf" => MonadicVerb::NormFrobenius,
+        "cond" => MonadicVerb::Cond,
+        "eye" => MonadicVerb::Eye,
+        "diag" => MonadicVerb::Diag,
+        "null" => MonadicVerb::NullSpace,
+        "col" => MonadicVerb::ColumnSpace,
+        "row" => MonadicVerb::RowSpace,
         _ => return None,
     };
 
@@ -102,6 +235,20 @@ fn parse_dyadic_verb<'a>(
         "@" => DyadicVerb::Dot,
         "++" => DyadicVerb::Plus,
         "**" => DyadicVerb::Times,
+        "!" => DyadicVerb::Scale,
+        "+" => DyadicVerb::Add,
+        "-" => DyadicVerb::Subtract,
+        "*" => DyadicVerb::Multiply,
+        "/" => DyadicVerb::Divide,
+        "^" => DyadicVerb::Power,
+        "\\" => DyadicVerb::Solve,
+        "lstsq" => DyadicVerb::LeastSquares,
+        "zeros" => DyadicVerb::Zeros,
+        "ones" => DyadicVerb::Ones,
+        "rand" => DyadicVerb::Random,
+        "mod" => DyadicVerb::Modulo,
+        "|" => DyadicVerb::HorizontalConcat,
+        "," => DyadicVerb::VerticalConcat,
         _ => return None,
     };
 
@@ -121,6 +268,21 @@ fn parse_cmd<'a>(cmd: Pair<'a, Rule>, cmd_params: Option<Pairs<'a, Rule>>) -> Op
     Some(AstNode::Command((cmd.as_str(), params)))
 }
 
+fn build_slice(pair: Pair<Rule>) -> Option<Slice> {
+    let mut lower = None;
+    let mut upper = None;
+    for bound in pair.into_inner() {
+        let rule = bound.as_rule();
+        let node = Box::new(build_ast_from_term(bound.into_inner().next()?)?);
+        match rule {
+            Rule::at => return Some(Slice::At(node)),
+            Rule::lower => lower = Some(node),
+            _ => upper = Some(node),
+        }
+    }
+    Some(Slice::Range(lower, upper))
+}
+
 fn build_ast_from_expr(pair: Pair<Rule>) -> Option<AstNode> {
     match pair.as_rule() {
         Rule::expr => build_ast_from_expr(pair.into_inner().next()?),
@@ -146,15 +308,51 @@ fn build_ast_from_expr(pair: Pair<Rule>) -> Option<AstNode> {
             let rhs = build_ast_from_expr(pair.next()?)?;
             parse_dyadic_verb(op, lhs, rhs)
         }
+        Rule::prefix => {
+            let mut pair = pair.into_inner();
+            let op = pair.next()?;
+            let lhs = build_ast_from_term(pair.next()?)?;
+            let rhs = build_ast_from_term(pair.next()?)?;
+            parse_dyadic_verb(op, lhs, rhs)
+        }
         Rule::assn => {
             let mut pair = pair.into_inner();
             let ident = pair.next()?;
             let expr = build_ast_from_expr(pair.next()?)?;
+            if ident.as_rule() == Rule::pattern {
+                return Some(AstNode::Destructure {
+                    idents: ident.into_inner().map(|i| i.as_str().to_string()).collect(),
+                    expr: Box::new(expr),
+                });
+            }
+            if ident.as_rule() == Rule::index {
+                let mut index = ident.into_inner();
+                return Some(AstNode::IndexAssignment {
+                    ident: index.next()?.as_str().to_string(),
+                    rows: build_slice(index.next()?)?,
+                    cols: build_slice(index.next()?)?,
+                    expr: Box::new(expr),
+                });
+            }
             Some(AstNode::Assignment {
                 ident: String::from(ident.as_str()),
                 expr: Box::new(expr),
             })
         }
+        Rule::index => {
+            let mut pair = pair.into_inner();
+            let ident = pair.next()?.as_str().to_string();
+            let rows = build_slice(pair.next()?)?;
+            let cols = build_slice(pair.next()?)?;
+            Some(AstNode::Index { ident, rows, cols })
+        }
+        Rule::tuple => {
+            let items = pair
+                .into_inner()
+                .map(build_ast_from_term)
+                .collect::<Option<Vec<_>>>()?;
+            Some(AstNode::Tuple(items))
+        }
         Rule::ident => {
             let i = pair.as_str();
             Some(AstNode::Ident(i.to_string()))
@@ -163,14 +361,10 @@ fn build_ast_from_expr(pair: Pair<Rule>) -> Option<AstNode> {
             let unparsed_terms = pair.into_inner();
             let mut terms: Vec<AstNode> = Vec::new();
             for ut in unparsed_terms {
-                let term = match build_ast_from_term(ut) {
-                    Some(t) => t,
-                    None => return None,
-                };
-                terms.push(term);
+                terms.push(build_ast_from_term(ut)?);
             }
             Some(match terms.len() {
-                1 => terms.get(0).unwrap().clone(),
+                1 => terms[0].clone(),
                 _ => Terms(terms),
             })
         }
@@ -179,11 +373,7 @@ fn build_ast_from_expr(pair: Pair<Rule>) -> Option<AstNode> {
             for row in pair.into_inner() {
                 let mut terms: Vec<AstNode> = Vec::new();
                 for ut in row.into_inner() {
-                    let term = match build_ast_from_term(ut) {
-                        Some(t) => t,
-                        None => return None,
-                    };
-                    terms.push(term);
+                    terms.push(build_ast_from_term(ut)?);
                 }
                 mat.push(terms);
             }
@@ -223,13 +413,35 @@ fn build_ast_from_expr(pair: Pair<Rule>) -> Option<AstNode> {
     }
 }
 
-pub fn parse(source: &str) -> Result<Vec<Box<AstNode>>, anyhow::Error> {
+fn integer(text: &str) -> Result<i32, anyhow::Error> {
+    text.parse()
+        .map_err(|e| anyhow!("{text} is not a valid integer: {e}"))
+}
+
+pub fn parse(source: &str) -> Result<Vec<Box<AstNode<'_>>>, anyhow::Error> {
     let mut ast = vec![];
 
     let pairs = LalaParser::parse(Rule::program, source)?;
+    if let Some(word) = pairs
+        .clone()
+        .flatten()
+        .find(|p| p.as_rule() == Rule::keyword)
+    {
+        return Err(anyhow!(
+            "{} is a reserved word and cannot be used as a name",
+            word.as_str()
+        ));
+    }
+    for literal in pairs
+        .clone()
+        .flatten()
+        .filter(|p| p.as_rule() == Rule::integer)
+    {
+        integer(literal.as_str())?;
+    }
     for pair in pairs {
         match pair.as_rule() {
-            Rule::fun_decl | Rule::expr => {
+            Rule::fun_decl | Rule::expr | Rule::command => {
                 let node = match build_ast_from_expr(pair) {
                     Some(n) => n,
                     None => return Err(anyhow!("Parse error! Please consult the guide :)")),
@@ -242,3 +454,96 @@ pub fn parse(source: &str) -> Result<Vec<Box<AstNode>>, anyhow::Error> {
 
     Ok(ast)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn literal(source: &str) -> AstNode<'_> {
+        match parse(source).unwrap().remove(0).as_ref() {
+            Terms(terms) if terms.len() == 1 => terms[0].clone(),
+            node => node.clone(),
+        }
+    }
+
+    /// The words in double quotes in rule `rule` of `lala.pest`, leaving out
+    /// symbols and comments.
+    fn grammar_words(rule: &str) -> BTreeSet<&'static str> {
+        let grammar = include_str!("lala.pest");
+        let rest = &grammar[grammar.find(&format!("\n{rule} = ")).unwrap() + 1..];
+        let end = if rest.lines().next().unwrap().ends_with('{') {
+            rest.find("\n}").unwrap()
+        } else {
+            rest.find('\n').unwrap()
+        };
+        rest[..end]
+            .lines()
+            .flat_map(|line| {
+                line.split("//")
+                    .next()
+                    .unwrap()
+                    .split('"')
+                    .skip(1)
+                    .step_by(2)
+            })
+            .filter(|word| word.starts_with(|c: char| c.is_ascii_alphabetic()))
+            .collect()
+    }
+
+    #[test]
+    fn keywords_match_the_grammar() {
+        let keywords: BTreeSet<&str> = KEYWORDS.iter().copied().collect();
+        assert_eq!(keywords.len(), KEYWORDS.len());
+        assert_eq!(grammar_words("keyword"), keywords);
+        let used: BTreeSet<&str> = ["LET", "FUN", "verb", "prefix_verb"]
+            .into_iter()
+            .flat_map(grammar_words)
+            .collect();
+        assert_eq!(used, keywords);
+        for word in KEYWORDS {
+            let error = parse(&format!("let {word} = 1")).unwrap_err();
+            assert!(
+                error.to_string().contains("reserved word"),
+                "{word}: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn complex_literals() {
+        let z = |re: f64, im: f64| Complex(Complex64::new(re, im));
+        assert_eq!(literal("3+2i"), z(3.0, 2.0));
+        assert_eq!(literal("-1.5-0.5i"), z(-1.5, -0.5));
+        assert_eq!(literal("2i"), z(0.0, 2.0));
+        assert_eq!(literal("-2i"), z(0.0, -2.0));
+        match literal("1 -2i") {
+            Matrix(rows) => assert_eq!(rows, vec![vec![Integer(1), z(0.0, -2.0)]]),
+            Terms(terms) => assert_eq!(terms, vec![Integer(1), z(0.0, -2.0)]),
+            node => panic!("{node:?} is not a row"),
+        }
+    }
+
+    #[test]
+    fn rational_literals() {
+        let ratio = |n: i32, d: i32| Rational(BigRational::new(n.into(), d.into()));
+        assert_eq!(literal("1/3"), ratio(1, 3));
+        assert_eq!(literal("-2/4"), ratio(-1, 2));
+        assert_eq!(literal("6/3"), ratio(2, 1));
+        // spaced out it is the division verb
+        assert!(matches!(
+            literal("1 / 3"),
+            DyadicOp {
+                verb: DyadicVerb::Divide,
+                ..
+            }
+        ));
+        assert!(matches!(
+            literal("3/0"),
+            DyadicOp {
+                verb: DyadicVerb::Divide,
+                ..
+            }
+        ));
+    }
+}
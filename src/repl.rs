@@ -1,23 +1,146 @@
-// use std::{
-//     collections::HashMap,
-//     io::{stdin, stdout, Write},
-// };
-
-// use super::interp::interp;
-// use super::parser;
-// use super::types::LalaType;
-
-// // pub fn repl() -> Result<(), anyhow::Error> {
-// //     let mut env: HashMap<String, LalaType> = HashMap::new();
-// //     println!("Lala Shell v0.0.1");
-// //     loop {
-// //         print!("\x1b[1mλ \x1b[0m");
-// //         stdout().flush()?;
-// //         let mut input = String::new();
-// //         stdin().read_line(&mut input)?;
-
-// //         let line = input.trim();
-// //         let ast = parser::parse(line)?;
-// //         interp(&ast, Some(&mut env), false)?;
-// //     }
-// // }
+use std::{collections::HashMap, path::PathBuf};
+
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::DefaultHistory, validate::Validator, Context, Editor, Helper,
+};
+
+use super::interp::{interp, Session};
+use super::parser::KEYWORDS;
+use super::types::LalaType;
+
+const HISTORY_FILE: &str = ".lala_history";
+
+/// Tab completes keywords and whatever is currently bound in the environment.
+/// `names` is refreshed from the environment before every prompt.
+#[derive(Default)]
+struct LalaHelper {
+    names: Vec<String>,
+}
+
+impl Completer for LalaHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .map_or(0, |i| i + 1);
+        let word = &line[start..pos];
+        if word.is_empty() {
+            return Ok((pos, vec![]));
+        }
+        let mut candidates: Vec<String> = KEYWORDS
+            .iter()
+            .map(|k| k.to_string())
+            .chain(self.names.iter().cloned())
+            .filter(|c| c.starts_with(word))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for LalaHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LalaHelper {}
+
+impl Validator for LalaHelper {}
+
+impl Helper for LalaHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Number of `{` still waiting for a matching `}`, so `fun ... => {` blocks
+/// can span several lines before being handed to the parser.
+fn open_braces(src: &str) -> i32 {
+    src.chars().fold(0, |depth, c| match c {
+        '{' => depth + 1,
+        '}' => depth - 1,
+        _ => depth,
+    })
+}
+
+/// Reads one complete statement, prompting for continuation lines while a
+/// brace is left open. Returns `None` on end of input.
+fn read_stmt(rl: &mut Editor<LalaHelper, DefaultHistory>) -> Result<Option<String>, anyhow::Error> {
+    let mut input = match rl.readline("λ ") {
+        Ok(line) => line,
+        Err(ReadlineError::Interrupted) => return Ok(Some(String::new())),
+        Err(ReadlineError::Eof) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    while open_braces(&input) > 0 {
+        match rl.readline(". ") {
+            Ok(more) => {
+                input.push('\n');
+                input.push_str(&more);
+            }
+            Err(ReadlineError::Interrupted) => return Ok(Some(String::new())),
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if !input.trim().is_empty() {
+        rl.add_history_entry(input.as_str())?;
+    }
+    Ok(Some(input))
+}
+
+pub fn repl() -> Result<(), anyhow::Error> {
+    let session = Session::default();
+    let mut env: HashMap<String, LalaType> = HashMap::new();
+    let mut rl: Editor<LalaHelper, DefaultHistory> = Editor::new()?;
+    rl.set_helper(Some(LalaHelper::default()));
+    let history = history_path();
+    if let Some(path) = &history {
+        // a missing history file just means this is the first session
+        let _ = rl.load_history(path);
+    }
+
+    println!("Lala Shell v0.0.1");
+    loop {
+        if let Some(helper) = rl.helper_mut() {
+            helper.names = env.keys().cloned().collect();
+        }
+        let input = match read_stmt(&mut rl)? {
+            Some(input) => input,
+            None => break,
+        };
+
+        let line = input.trim();
+        if line.is_empty() {
+            continue;
+        }
+        // written as we go, so a crash or a kill keeps the session's history
+        if let Some(path) = &history {
+            if let Err(e) = rl.append_history(path) {
+                eprintln!("Could not save history: {}", e);
+            }
+        }
+
+        let ast = match session.parse(line) {
+            Ok(ast) => ast,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                continue;
+            }
+        };
+
+        match interp(ast, Some(&mut env), true, &session) {
+            Ok(res) if res.is_empty() => {}
+            Ok(res) => println!("{}", res.trim_end()),
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+    Ok(())
+}
@@ -1,32 +1,69 @@
-use super::linalg::Matrix;
+use super::field::{Field, Modular};
+use super::linalg::{Matrix, RowOp};
 use super::parser::{AstNode, DyadicVerb, MonadicVerb};
 use anyhow::{anyhow, Error};
+use num_complex::Complex64;
+use num_rational::BigRational;
+use num_traits::Signed;
+use std::cell::Cell;
 use std::fmt::Display;
 
 #[derive(Clone, Debug)]
 pub enum LalaType<'a> {
     Integer(i32),
     Double(f64),
+    Rational(BigRational),
+    Complex(Complex64),
     Matrix(Matrix),
-    Fun((String, Vec<AstNode<'a>>, Vec<AstNode<'a>>)),
+    RationalMatrix(Matrix<BigRational>),
+    ComplexMatrix(Matrix<Complex64>),
+    Modular(Modular),
+    ModularMatrix(Matrix<Modular>),
+    /// The trace `rref_steps` leaves: the starting matrix, then each row
+    /// operation with the matrix right after it.
+    Steps {
+        start: Box<LalaType<'a>>,
+        steps: Vec<(RowOp<LalaType<'a>>, LalaType<'a>)>,
+    },
+    Tuple(Vec<LalaType<'a>>),
+    /// Name, parameters and body, borrowed from the syntax tree the
+    /// function was declared in.
+    Fun((String, &'a [AstNode<'a>], &'a [AstNode<'a>])),
 }
 
 impl Display for LalaType<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            LalaType::Integer(i) => write!(f, "{}", i.to_string())?,
-            LalaType::Double(d) => write!(f, "{}", d.to_string())?,
-            LalaType::Matrix(m) => {
-                for r in 0..m.rows {
-                    write!(f, "[")?;
-                    for c in 0..m.cols {
-                        if c == m.cols - 1 {
-                            write!(f, "{:.2}", m[r][c])?;
-                        } else {
-                            write!(f, "{:.2} ", m[r][c])?;
+            LalaType::Integer(i) => write!(f, "{}", i)?,
+            LalaType::Double(d) => write!(f, "{}", d)?,
+            LalaType::Rational(q) => write!(f, "{}", rational_entry(q))?,
+            LalaType::Complex(z) => write!(f, "{}", z)?,
+            LalaType::Modular(x) => write!(f, "{}", x)?,
+            LalaType::Matrix(m) => write_matrix(f, m, float_entry)?,
+            LalaType::RationalMatrix(m) => write_matrix(f, m, rational_entry)?,
+            LalaType::ComplexMatrix(m) => write_matrix(f, m, complex_entry)?,
+            LalaType::ModularMatrix(m) => write_matrix(f, m, |x| x.to_string())?,
+            LalaType::Steps { start, steps } => {
+                write!(f, "{start}")?;
+                for (op, matrix) in steps {
+                    match output_format() {
+                        Format::Latex => {
+                            write!(f, "\\xrightarrow{{{}}}\n{matrix}", latex_row_op(op))?
+                        }
+                        // a table has to start a block of its own
+                        Format::Markdown => write!(f, "\n{}\n\n{matrix}", plain_row_op(op))?,
+                        Format::Plain | Format::Full => {
+                            write!(f, "\n{}\n{matrix}", plain_row_op(op))?
                         }
                     }
-                    writeln!(f, "]")?;
+                }
+            }
+            LalaType::Tuple(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", value)?;
                 }
             }
             LalaType::Fun((name, param_list, _body)) => {
@@ -51,41 +88,295 @@ impl Display for LalaType<'_> {
     }
 }
 
-impl ToString for MonadicVerb {
-    fn to_string(&self) -> String {
-        match self {
-            MonadicVerb::Rank => String::from("matrix rank"),
-            MonadicVerb::Inverse => String::from("matrix inverse"),
-            MonadicVerb::RREF => String::from("matrix rref"),
-            MonadicVerb::Transpose => String::from("matrix transpose"),
-            MonadicVerb::Determinant => String::from("matrix determinant"),
+/// How values are printed, set with `--format` or `/format`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    /// Rows in brackets, floats to two decimals.
+    Plain,
+    /// Matrices as `bmatrix` environments, fractions as `\frac`.
+    Latex,
+    /// Matrices as tables with an empty header row.
+    Markdown,
+    /// Like plain, with floats printed in full.
+    Full,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "plain" => Format::Plain,
+            "latex" => Format::Latex,
+            "markdown" => Format::Markdown,
+            "full" => Format::Full,
+            _ => return None,
+        })
+    }
+}
+
+thread_local! {
+    pub static FORMAT: Cell<Format> = const { Cell::new(Format::Plain) };
+}
+
+pub fn output_format() -> Format {
+    FORMAT.with(|format| format.get())
+}
+
+fn float_entry(x: &f64) -> String {
+    match output_format() {
+        Format::Full => x.to_string(),
+        _ => format!("{:.2}", x),
+    }
+}
+
+fn complex_entry(z: &Complex64) -> String {
+    // the sign goes between the parts, so -0.00 never shows as +-0.00
+    let sign = if z.im < 0.0 { '-' } else { '+' };
+    format!("{}{sign}{}i", float_entry(&z.re), float_entry(&z.im.abs()))
+}
+
+fn rational_entry(q: &BigRational) -> String {
+    if output_format() != Format::Latex || q.is_integer() {
+        return q.to_string();
+    }
+    let sign = if q.is_negative() { "-" } else { "" };
+    format!("{sign}\\frac{{{}}}{{{}}}", q.numer().abs(), q.denom())
+}
+
+/// A scalar the way it appears inside a matrix.
+fn scalar_text(value: &LalaType) -> String {
+    match value {
+        LalaType::Double(d) => float_entry(d),
+        LalaType::Complex(z) => complex_entry(z),
+        other => other.to_string(),
+    }
+}
+
+/// `- factor` written out, dropping a factor of 1, folding a leading minus
+/// into the sign and bracketing a factor that has a sign of its own inside.
+fn minus(factor: &str) -> String {
+    match factor.strip_prefix('-') {
+        _ if factor == "1" => String::from("-"),
+        Some("1") => String::from("+"),
+        Some(rest) if !rest.contains(['+', '-']) => format!("+ {rest}"),
+        _ if factor.contains(['+', '-']) => format!("- ({factor})"),
+        _ => format!("- {factor}"),
+    }
+}
+
+/// Like `R2 <- R2 - 3 R1`, rows counted from 1 as on paper.
+fn plain_row_op(op: &RowOp<LalaType>) -> String {
+    match op {
+        RowOp::Swap(a, b) => format!("R{} <-> R{}", a + 1, b + 1),
+        RowOp::Scale { row, divisor } => {
+            let divisor = scalar_text(divisor);
+            let divisor = if divisor.contains(['+', '-', '/']) {
+                format!("({divisor})")
+            } else {
+                divisor
+            };
+            format!("R{0} <- R{0} / {divisor}", row + 1)
         }
+        RowOp::Subtract {
+            target,
+            source,
+            factor,
+        } => format!(
+            "R{0} <- R{0} {1} R{2}",
+            target + 1,
+            minus(&scalar_text(factor)),
+            source + 1
+        ),
     }
 }
 
-impl ToString for DyadicVerb {
-    fn to_string(&self) -> String {
-        match self {
-            DyadicVerb::Dot => String::from("dot product"),
-            DyadicVerb::Plus => String::from("matrix addition"),
-            DyadicVerb::Times => String::from("matrix multiplication"),
+fn latex_row_op(op: &RowOp<LalaType>) -> String {
+    match op {
+        RowOp::Swap(a, b) => format!("R_{{{}}} \\leftrightarrow R_{{{}}}", a + 1, b + 1),
+        RowOp::Scale { row, divisor } => {
+            let divisor = scalar_text(divisor);
+            let divisor = if divisor.contains(['+', '-', '\\']) {
+                format!("\\left({divisor}\\right)")
+            } else {
+                divisor
+            };
+            format!("R_{{{0}}} \\to R_{{{0}}} / {divisor}", row + 1)
+        }
+        RowOp::Subtract {
+            target,
+            source,
+            factor,
+        } => format!(
+            "R_{{{0}}} \\to R_{{{0}}} {1} R_{{{2}}}",
+            target + 1,
+            minus(&scalar_text(factor)),
+            source + 1
+        ),
+    }
+}
+
+/// Writes `m` in the current output format, converting entries with `entry`.
+fn write_matrix<T>(
+    f: &mut std::fmt::Formatter<'_>,
+    m: &Matrix<T>,
+    entry: impl Fn(&T) -> String,
+) -> std::fmt::Result {
+    let rows: Vec<Vec<String>> = (0..m.rows)
+        .map(|r| m[r].iter().map(&entry).collect())
+        .collect();
+    match output_format() {
+        Format::Plain | Format::Full => {
+            for row in rows {
+                writeln!(f, "[{}]", row.join(" "))?;
+            }
+        }
+        Format::Latex => {
+            let rows: Vec<String> = rows.iter().map(|row| row.join(" & ")).collect();
+            writeln!(
+                f,
+                "\\begin{{bmatrix}} {} \\end{{bmatrix}}",
+                rows.join(" \\\\ ")
+            )?;
+        }
+        Format::Markdown => {
+            writeln!(f, "|{}", "   |".repeat(m.cols))?;
+            writeln!(f, "|{}", "---|".repeat(m.cols))?;
+            for row in rows {
+                writeln!(f, "| {} |", row.join(" | "))?;
+            }
         }
     }
+    Ok(())
 }
 
-// later, return result<matrix, error>
-pub fn construct_matrix(v: &Vec<Vec<AstNode>>) -> Result<Matrix, Error> {
+impl From<f64> for LalaType<'_> {
+    fn from(d: f64) -> Self {
+        LalaType::Double(d)
+    }
+}
+
+impl From<Matrix> for LalaType<'_> {
+    fn from(m: Matrix) -> Self {
+        LalaType::Matrix(m)
+    }
+}
+
+impl From<BigRational> for LalaType<'_> {
+    fn from(q: BigRational) -> Self {
+        LalaType::Rational(q)
+    }
+}
+
+impl From<Matrix<BigRational>> for LalaType<'_> {
+    fn from(m: Matrix<BigRational>) -> Self {
+        LalaType::RationalMatrix(m)
+    }
+}
+
+impl From<Complex64> for LalaType<'_> {
+    fn from(z: Complex64) -> Self {
+        LalaType::Complex(z)
+    }
+}
+
+impl From<Matrix<Complex64>> for LalaType<'_> {
+    fn from(m: Matrix<Complex64>) -> Self {
+        LalaType::ComplexMatrix(m)
+    }
+}
+
+impl From<Modular> for LalaType<'_> {
+    fn from(x: Modular) -> Self {
+        LalaType::Modular(x)
+    }
+}
+
+impl From<Matrix<Modular>> for LalaType<'_> {
+    fn from(m: Matrix<Modular>) -> Self {
+        LalaType::ModularMatrix(m)
+    }
+}
+
+impl Display for MonadicVerb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            MonadicVerb::Rank => "matrix rank",
+            MonadicVerb::Inverse => "matrix inverse",
+            MonadicVerb::Rref => "matrix rref",
+            MonadicVerb::RrefSteps => "rref trace",
+            MonadicVerb::Transpose => "matrix transpose",
+            MonadicVerb::ConjugateTranspose => "conjugate transpose",
+            MonadicVerb::Determinant => "matrix determinant",
+            MonadicVerb::Lu => "lu decomposition",
+            MonadicVerb::Qr => "qr decomposition",
+            MonadicVerb::Eigen => "eigendecomposition",
+            MonadicVerb::Svd => "singular value decomposition",
+            MonadicVerb::PseudoInverse => "pseudoinverse",
+            MonadicVerb::Cholesky => "cholesky factorization",
+            MonadicVerb::Ldl => "ldl factorization",
+            MonadicVerb::Trace => "trace",
+            MonadicVerb::Norm => "spectral norm",
+            MonadicVerb::Norm1 => "1-norm",
+            MonadicVerb::NormInf => "infinity norm",
+            MonadicVerb::NormFrobenius => "frobenius norm",
+            MonadicVerb::Cond => "condition number",
+            MonadicVerb::Eye => "identity matrix",
+            MonadicVerb::Diag => "diagonal",
+            MonadicVerb::NullSpace => "null space",
+            MonadicVerb::ColumnSpace => "column space",
+            MonadicVerb::RowSpace => "row space",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Display for DyadicVerb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DyadicVerb::Dot => "dot product",
+            DyadicVerb::Plus => "matrix addition",
+            DyadicVerb::Times => "matrix multiplication",
+            DyadicVerb::Scale => "scalar multiplication",
+            DyadicVerb::Add => "addition",
+            DyadicVerb::Subtract => "subtraction",
+            DyadicVerb::Multiply => "multiplication",
+            DyadicVerb::Divide => "division",
+            DyadicVerb::Power => "exponentiation",
+            DyadicVerb::Solve => "linear solve",
+            DyadicVerb::LeastSquares => "least squares",
+            DyadicVerb::Zeros => "zero matrix",
+            DyadicVerb::Ones => "ones matrix",
+            DyadicVerb::Random => "random matrix",
+            DyadicVerb::Modulo => "reduction mod p",
+            DyadicVerb::HorizontalConcat => "horizontal concatenation",
+            DyadicVerb::VerticalConcat => "vertical concatenation",
+        };
+        write!(f, "{name}")
+    }
+}
+
+pub fn construct_matrix<T: Field>(v: &[Vec<AstNode>]) -> Result<Matrix<T>, Error> {
     let rows = v.len();
-    let cols = v[0].len();
-    let mut mat: Vec<f64> = vec![0.0; rows * cols];
+    let cols = v.first().map_or(0, Vec::len);
+    if let Some(ragged) = v.iter().position(|row| row.len() != cols) {
+        return Err(anyhow!(
+            "Row {} has {} entries, but the first row has {cols}",
+            ragged + 1,
+            v[ragged].len()
+        ));
+    }
+    let mut mat: Vec<T> = vec![T::zero(); rows * cols];
 
     for row in 0..rows {
         for col in 0..cols {
-            match &v[row][col] {
-                AstNode::Integer(i) => mat[row * cols + col] = *i as f64,
-                AstNode::DoublePrecisionFloat(d) => mat[row * cols + col] = *d,
+            let entry = match &v[row][col] {
+                AstNode::Integer(i) => Some(T::from_integer(*i)),
+                AstNode::DoublePrecisionFloat(d) => T::from_double(*d),
+                AstNode::Rational(q) => T::from_rational(q),
+                AstNode::Complex(z) => T::from_complex(z),
                 err => return Err(anyhow!("{:?} not allowed in matrix definition", err)),
-            }
+            };
+            mat[row * cols + col] =
+                entry.ok_or_else(|| anyhow!("{:?} has no value here", v[row][col]))?;
         }
     }
     Ok(Matrix {